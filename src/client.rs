@@ -3,41 +3,157 @@ use crate::Error;
 use crate::ErrorCode;
 use crate::Response;
 use crate::ResponseData;
+use crate::Transport;
+use bytes::Bytes;
 use rand::Rng;
+#[cfg(feature = "reqwest")]
+use std::net::SocketAddr;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 use url::Url;
 
+/// The host of the api
+pub const API_HOST: &str = "g.api.mega.co.nz";
+
+/// The base delay between retries, in milliseconds
+const BASE_RETRY_DELAY_MILLIS: u64 = 250;
+
+/// The maximum number of retries
+const MAX_RETRIES: u32 = 3;
+
+/// The maximum length of a response body snippet included in errors
+const MAX_SNIPPET_LEN: usize = 256;
+
 /// A client
+///
+/// Cloning is cheap, and clones share the same connection pool and sequence id.
 #[derive(Debug, Clone)]
 pub struct Client {
-    /// The inner http client
+    /// The reqwest client, for downloading files.
+    ///
+    /// This is not used for api requests, which are sent with `transport`.
+    /// Clients made with a custom transport get a default reqwest client.
+    #[cfg(feature = "reqwest")]
     pub client: reqwest::Client,
 
+    /// The transport used to send api requests
+    pub transport: Arc<dyn Transport>,
+
     /// The sequence id
     pub sequence_id: Arc<AtomicU64>,
+
+    /// Whether to randomize retry delays.
+    ///
+    /// When enabled, each retry waits a random duration up to the exponential backoff delay,
+    /// so that many clients do not retry in lockstep.
+    /// Defaults to true.
+    pub retry_jitter: bool,
+
+    /// Whether to fail when a response contains fields that are not recognized.
+    ///
+    /// This is useful in tests to detect api changes early.
+    /// Defaults to false, collecting unknown fields into the `unknown` field of each response.
+    pub deny_unknown_fields: bool,
+
+    /// Whether to send api requests over plain http, with the `ssl=0` parameter.
+    ///
+    /// WARNING: This disables all transport security.
+    /// Anyone on the network can read and modify requests and responses, including download urls.
+    /// Only enable this as a last resort in networks where https to the api is broken.
+    /// Defaults to false.
+    pub allow_insecure: bool,
 }
 
 impl Client {
     /// Make a new client
+    #[cfg(feature = "reqwest")]
     pub fn new() -> Self {
+        Self::from_reqwest_client(reqwest::Client::new())
+    }
+
+    /// Make a new client from a reqwest client.
+    ///
+    /// This allows customizing the http client, like its dns resolution, proxies, and timeouts.
+    #[cfg(feature = "reqwest")]
+    pub fn from_reqwest_client(client: reqwest::Client) -> Self {
         Self {
+            transport: Arc::new(client.clone()),
+            client,
+            sequence_id: Arc::new(AtomicU64::new(rand::thread_rng().gen())),
+            retry_jitter: true,
+            deny_unknown_fields: false,
+            allow_insecure: false,
+        }
+    }
+
+    /// Make a new client that connects to the given addresses for the api host,
+    /// instead of resolving it with dns.
+    ///
+    /// This is useful in networks where dns resolution of the api host is blocked or unreliable.
+    #[cfg(feature = "reqwest")]
+    pub fn with_api_host_addrs(addrs: &[SocketAddr]) -> Result<Self, Error> {
+        let client = reqwest::Client::builder()
+            .resolve_to_addrs(API_HOST, addrs)
+            .build()?;
+        Ok(Self::from_reqwest_client(client))
+    }
+
+    /// Make a new client with the given starting sequence id.
+    ///
+    /// By default, the sequence id is random.
+    #[cfg(feature = "reqwest")]
+    pub fn with_sequence_id(sequence_id: u64) -> Self {
+        let client = Self::new();
+        client.set_sequence_id(sequence_id);
+        client
+    }
+
+    /// Make a new client that sends api requests with the given transport.
+    pub fn with_transport<T>(transport: T) -> Self
+    where
+        T: Transport + 'static,
+    {
+        Self {
+            #[cfg(feature = "reqwest")]
             client: reqwest::Client::new(),
+            transport: Arc::new(transport),
             sequence_id: Arc::new(AtomicU64::new(rand::thread_rng().gen())),
+            retry_jitter: true,
+            deny_unknown_fields: false,
+            allow_insecure: false,
         }
     }
 
+    /// Get the sequence id that will be used for the next request.
+    ///
+    /// The id sent to the server is this value modulo 100,000.
+    pub fn sequence_id(&self) -> u64 {
+        self.sequence_id.load(Ordering::Relaxed)
+    }
+
+    /// Set the sequence id that will be used for the next request.
+    ///
+    /// This is shared with all clones of this client.
+    /// This is useful for deterministic tests, with any transport.
+    pub fn set_sequence_id(&self, sequence_id: u64) {
+        self.sequence_id.store(sequence_id, Ordering::Relaxed);
+    }
+
     /// Execute a series of commands.
+    ///
+    /// The returned responses are in the same order as the commands,
+    /// and there is always exactly one response per command.
     pub async fn execute_commands(
         &self,
         commands: &[Command],
         node: Option<&str>,
     ) -> Result<Vec<Response<ResponseData>>, Error> {
         let id = self.sequence_id.fetch_add(1, Ordering::Relaxed) % 100_000;
+        let scheme = if self.allow_insecure { "http" } else { "https" };
         let mut url = Url::parse_with_params(
-            "https://g.api.mega.co.nz/cs",
+            &format!("{scheme}://{API_HOST}/cs"),
             &[("id", itoa::Buffer::new().format(id))],
         )?;
         {
@@ -45,23 +161,35 @@ impl Client {
             if let Some(node) = node {
                 query_pairs.append_pair("n", node);
             }
+            if self.allow_insecure {
+                query_pairs.append_pair("ssl", "0");
+            }
         }
 
+        let body = Bytes::from(serde_json::to_vec(commands)?);
+
         let mut retries = 0;
         let response = loop {
-            let response: Response<Vec<_>> = self
-                .client
-                .post(url.as_str())
-                .json(commands)
-                .send()
-                .await?
-                .error_for_status()?
-                .json()
-                .await?;
+            let response = self.transport.post_json(&url, body.clone()).await?;
+
+            // Mega sometimes serves html error pages, like during outages.
+            // Report these instead of a cryptic json parse error.
+            if response.trim_ascii_start().starts_with(b"<") {
+                let snippet = &response[..std::cmp::min(response.len(), MAX_SNIPPET_LEN)];
+                return Err(Error::UnexpectedResponseBody {
+                    snippet: String::from_utf8_lossy(snippet).into_owned(),
+                });
+            }
+
+            let response: Response<Vec<Response<ResponseData>>> =
+                serde_json::from_slice(&response)?;
             let response = response.into_result();
 
-            if retries < 3 && matches!(response, Err(ErrorCode::EAGAIN)) {
-                let millis = 250 * (1 << retries);
+            if retries < MAX_RETRIES && matches!(response, Err(ErrorCode::EAGAIN)) {
+                let mut millis = BASE_RETRY_DELAY_MILLIS * (1 << retries);
+                if self.retry_jitter {
+                    millis = rand::thread_rng().gen_range(0..=millis);
+                }
                 tokio::time::sleep(Duration::from_millis(millis)).await;
                 retries += 1;
                 continue;
@@ -80,10 +208,25 @@ impl Client {
             });
         }
 
+        if self.deny_unknown_fields {
+            let fields: Vec<_> = response
+                .iter()
+                .filter_map(|response| match response {
+                    Response::Ok(data) => Some(data.unknown_fields()),
+                    Response::Error(_) => None,
+                })
+                .flatten()
+                .collect();
+            if !fields.is_empty() {
+                return Err(Error::UnknownFields { fields });
+            }
+        }
+
         Ok(response)
     }
 }
 
+#[cfg(feature = "reqwest")]
 impl Default for Client {
     fn default() -> Self {
         Self::new()
@@ -98,19 +241,35 @@ mod test {
 
     #[tokio::test]
     async fn execute_empty_commands() {
-        let client = Client::new();
+        let transport = MockTransport::new(["[]"]);
+        let client = Client::with_transport(transport.clone());
         let response = client
             .execute_commands(&[], None)
             .await
             .expect("failed to execute commands");
         assert!(response.is_empty());
+
+        let requests = transport.requests.lock().unwrap();
+        assert!(requests.len() == 1);
+        let (url, body) = &requests[0];
+        assert!(url.path() == "/cs");
+        assert!(url.query_pairs().any(|(key, _)| key == "id"));
+        assert!(&body[..] == b"[]");
     }
 
     #[tokio::test]
     async fn execute_get_attributes_command() {
-        let client = Client::new();
+        let transport = MockTransport::new([
+            format!("[{}]", get_attributes_response(1234, None)),
+            format!(
+                "[{}]",
+                get_attributes_response(1234, Some("https://example.com/dl/test"))
+            ),
+        ]);
+        let client = Client::with_transport(transport.clone());
         let commands = vec![Command::GetAttributes {
-            file_id: TEST_FILE_ID.into(),
+            public_file_id: Some(TEST_FILE_ID.into()),
+            node_id: None,
             include_download_url: None,
         }];
         let mut response = client
@@ -124,6 +283,7 @@ mod test {
             ResponseData::GetAttributes(response) => response,
             _ => panic!("unexpected response"),
         };
+        assert!(response.size == 1234);
         assert!(response.download_url.is_none());
         let file_attributes = response
             .decode_attributes(TEST_FILE_KEY_KEY_DECODED)
@@ -131,7 +291,8 @@ mod test {
         assert!(file_attributes.name == "Doxygen_docs.zip");
 
         let commands = vec![Command::GetAttributes {
-            file_id: TEST_FILE_ID.into(),
+            public_file_id: Some(TEST_FILE_ID.into()),
+            node_id: None,
             include_download_url: Some(1),
         }];
         let mut response = client
@@ -150,8 +311,198 @@ mod test {
             .decode_attributes(TEST_FILE_KEY_KEY_DECODED)
             .expect("failed to decode attributes");
         assert!(file_attributes.name == "Doxygen_docs.zip");
+
+        // Public file lookups are not made relative to a folder.
+        let requests = transport.requests.lock().unwrap();
+        assert!(requests.len() == 2);
+        assert!(requests
+            .iter()
+            .all(|(url, _)| !url.query_pairs().any(|(key, _)| key == "n")));
+        assert!(&requests[0].1[..] == br#"[{"a":"g","p":"7glwEQBT","g":null}]"#);
+        assert!(&requests[1].1[..] == br#"[{"a":"g","p":"7glwEQBT","g":1}]"#);
+    }
+
+    #[tokio::test]
+    async fn execute_get_attributes_folder_child_command() {
+        let transport = MockTransport::new([format!("[{}]", get_attributes_response(1234, None))]);
+        let client = Client::with_transport(transport.clone());
+        let commands = vec![Command::GetAttributes {
+            public_file_id: None,
+            node_id: Some("AAAAAAAA".into()),
+            include_download_url: None,
+        }];
+        let mut response = client
+            .execute_commands(&commands, Some(TEST_FOLDER_ID))
+            .await
+            .expect("failed to execute commands");
+        assert!(response.len() == 1);
+        let response = response.swap_remove(0);
+        assert!(matches!(
+            response.into_result(),
+            Ok(ResponseData::GetAttributes(_))
+        ));
+
+        let requests = transport.requests.lock().unwrap();
+        assert!(requests.len() == 1);
+        assert!(requests[0]
+            .0
+            .query_pairs()
+            .any(|(key, value)| key == "n" && value == TEST_FOLDER_ID));
+        assert!(&requests[0].1[..] == br#"[{"a":"g","n":"AAAAAAAA","g":null}]"#);
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[test]
+    fn with_sequence_id() {
+        let client = Client::with_sequence_id(1234);
+        assert!(client.sequence_id() == 1234);
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[test]
+    fn with_api_host_addrs() {
+        let addrs = ["[2001:db8::1]:443".parse().unwrap()];
+        Client::with_api_host_addrs(&addrs).expect("failed to make client");
+    }
+
+    #[tokio::test]
+    async fn execute_commands_sequence_id() {
+        let transport = MockTransport::new(["[]", "[]"]);
+        let client = Client::with_transport(transport.clone());
+        client.set_sequence_id(100_001);
+        for _ in 0..2 {
+            client
+                .execute_commands(&[], None)
+                .await
+                .expect("failed to execute commands");
+        }
+        assert!(client.sequence_id() == 100_003);
+
+        let requests = transport.requests.lock().unwrap();
+        let ids: Vec<_> = requests
+            .iter()
+            .map(|(url, _)| {
+                url.query_pairs()
+                    .find(|(key, _)| key == "id")
+                    .expect("missing id")
+                    .1
+                    .into_owned()
+            })
+            .collect();
+        assert!(ids == ["1", "2"]);
     }
 
+    #[tokio::test]
+    async fn execute_commands_response_order() {
+        let fetch_nodes_response = TEST_FETCH_NODES_RESPONSE
+            .strip_prefix('[')
+            .and_then(|response| response.strip_suffix(']'))
+            .unwrap();
+        let transport = MockTransport::new([format!(
+            "[{fetch_nodes_response},{},-9]",
+            get_attributes_response(1234, None)
+        )]);
+        let client = Client::with_transport(transport);
+        let commands = vec![
+            Command::FetchNodes { c: 1, r: 1 },
+            Command::GetAttributes {
+                public_file_id: Some(TEST_FILE_ID.into()),
+                node_id: None,
+                include_download_url: None,
+            },
+            Command::GetAttributes {
+                public_file_id: Some("AAAAAAAA".into()),
+                node_id: None,
+                include_download_url: None,
+            },
+        ];
+        let response = client
+            .execute_commands(&commands, None)
+            .await
+            .expect("failed to execute commands");
+        assert!(response.len() == 3);
+
+        let mut response = response.into_iter();
+        assert!(matches!(
+            response.next().unwrap(),
+            Response::Ok(ResponseData::FetchNodes(_))
+        ));
+        assert!(matches!(
+            response.next().unwrap(),
+            Response::Ok(ResponseData::GetAttributes(GetAttributesResponse {
+                size: 1234,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            response.next().unwrap(),
+            Response::Error(ErrorCode::ENOENT)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn execute_commands_retries_eagain() {
+        let transport = MockTransport::new(["-3", "[]"]);
+        let client = Client::with_transport(transport.clone());
+        let response = client
+            .execute_commands(&[], None)
+            .await
+            .expect("failed to execute commands");
+        assert!(response.is_empty());
+        assert!(transport.requests.lock().unwrap().len() == 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn execute_commands_retries_eagain_without_jitter() {
+        let transport = MockTransport::new(["-3", "-3", "[]"]);
+        let mut client = Client::with_transport(transport.clone());
+        client.retry_jitter = false;
+
+        let start = tokio::time::Instant::now();
+        client
+            .execute_commands(&[], None)
+            .await
+            .expect("failed to execute commands");
+
+        // Time is paused, so the elapsed time is exactly the sum of the backoff delays.
+        assert!(start.elapsed() == Duration::from_millis(BASE_RETRY_DELAY_MILLIS * 3));
+        assert!(transport.requests.lock().unwrap().len() == 3);
+    }
+
+    #[tokio::test]
+    async fn execute_commands_html_response() {
+        let client = Client::with_transport(MockTransport::new([
+            "\n<!DOCTYPE html><html><body>Service Unavailable</body></html>",
+        ]));
+        let error = client
+            .execute_commands(&[], None)
+            .await
+            .expect_err("html response was not detected");
+        match error {
+            Error::UnexpectedResponseBody { snippet } => {
+                assert!(snippet.contains("Service Unavailable"));
+            }
+            error => panic!("unexpected error {error:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn execute_commands_response_length_mismatch() {
+        let client = Client::with_transport(MockTransport::new(["[-9]"]));
+        let error = client
+            .execute_commands(&[], None)
+            .await
+            .expect_err("response length mismatch was not detected");
+        assert!(matches!(
+            error,
+            Error::ResponseLengthMismatch {
+                expected: 0,
+                actual: 1
+            }
+        ));
+    }
+
+    #[cfg(feature = "reqwest")]
     #[tokio::test]
     async fn execute_fetch_nodes_command() {
         let folder_key = FolderKey(TEST_FOLDER_KEY_DECODED);
@@ -169,20 +520,65 @@ mod test {
             ResponseData::FetchNodes(response) => response,
             _ => panic!("unexpected response"),
         };
-        assert!(response.files.len() == 3);
-        let file_attributes = response.files[0]
+        assert!(response.nodes.len() == 3);
+        let file_attributes = response.nodes[0]
             .decode_attributes(&folder_key)
             .expect("failed to decode attributes");
         assert!(file_attributes.name == "test");
 
-        let file_attributes = dbg!(&response.files[1])
+        let file_attributes = dbg!(&response.nodes[1])
             .decode_attributes(&folder_key)
             .expect("failed to decode attributes");
         assert!(file_attributes.name == "test.txt");
 
-        let file_attributes = dbg!(&response.files[2])
+        let file_attributes = dbg!(&response.nodes[2])
             .decode_attributes(&folder_key)
             .expect("failed to decode attributes");
         assert!(file_attributes.name == "testfolder");
     }
+
+    #[tokio::test]
+    async fn execute_commands_deny_unknown_fields() {
+        let response = r#"[{"p":"https://example.com/ul/test","new":1}]"#;
+        let transport = MockTransport::new([response, response]);
+        let mut client = Client::with_transport(transport);
+        let commands = [Command::GetUploadUrl { size: 1234 }];
+
+        client
+            .execute_commands(&commands, None)
+            .await
+            .expect("failed to execute commands");
+
+        client.deny_unknown_fields = true;
+        let error = client
+            .execute_commands(&commands, None)
+            .await
+            .expect_err("unknown fields should be denied");
+        assert!(matches!(error, Error::UnknownFields { fields } if fields == ["new"]));
+    }
+
+    #[tokio::test]
+    async fn execute_commands_allow_insecure() {
+        let transport = MockTransport::new(["[]", "[]"]);
+        let mut client = Client::with_transport(transport.clone());
+
+        client
+            .execute_commands(&[], None)
+            .await
+            .expect("failed to execute commands");
+        client.allow_insecure = true;
+        client
+            .execute_commands(&[], None)
+            .await
+            .expect("failed to execute commands");
+
+        let requests = transport.requests.lock().unwrap();
+        assert!(requests[0].0.scheme() == "https");
+        assert!(!requests[0].0.query_pairs().any(|(key, _)| key == "ssl"));
+        assert!(requests[1].0.scheme() == "http");
+        assert!(requests[1]
+            .0
+            .query_pairs()
+            .any(|(key, value)| key == "ssl" && value == "0"));
+    }
 }
@@ -1,16 +1,35 @@
 use crate::Command;
+use crate::DecodeAttributesError;
+use crate::DecodedNode;
 use crate::Error;
+use crate::ErrorCode;
 use crate::FetchNodesResponse;
+use crate::FileAttributes;
+use crate::FileKey;
+use crate::FolderKey;
 use crate::GetAttributesResponse;
 use crate::ResponseData;
+use crate::UploadUrlResponse;
+use std::collections::HashMap;
 use std::future::Future;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use url::Url;
+
+/// The delay before retrying a get attributes request that is missing the download url
+const DOWNLOAD_URL_RETRY_DELAY: Duration = Duration::from_millis(250);
 
 /// A client
+///
+/// Cloning is cheap, and clones share the same connection pool, command queue, and cache.
+/// Prefer cloning an existing client over making a new one for each task.
 #[derive(Debug, Clone)]
 pub struct Client {
-    /// The low-level api client
+    /// The low-level api client.
+    ///
+    /// See `low_level`.
     pub client: crate::Client,
 
     /// Client state
@@ -19,89 +38,174 @@ pub struct Client {
 
 impl Client {
     /// Make a new client
+    #[cfg(feature = "reqwest")]
     pub fn new() -> Self {
+        Self::from_client(crate::Client::new())
+    }
+
+    /// Make a new client from a low-level api client
+    pub fn from_client(client: crate::Client) -> Self {
         Self {
-            client: crate::Client::new(),
+            client,
             state: Arc::new(Mutex::new(State {
-                buffered_commands: Vec::with_capacity(4),
-                buffered_tx: Vec::with_capacity(4),
+                buffered: HashMap::new(),
+                download_url_retries: 1,
+                fetch_nodes_cache_ttl: None,
+                fetch_nodes_cache: HashMap::new(),
             })),
         }
     }
 
+    /// Get the low-level api client.
+    ///
+    /// Use this to send commands that this client does not wrap.
+    /// Commands sent this way bypass batching and caching, but share the sequence id and connection pool.
+    pub fn low_level(&self) -> &crate::Client {
+        &self.client
+    }
+
     /// Queue a command to be sent
+    ///
+    /// Commands are batched by reference node id,
+    /// as the reference node id applies to an entire batch.
     fn queue_command(
         &self,
         command: Command,
+        reference_node_id: Option<String>,
     ) -> tokio::sync::oneshot::Receiver<Result<ResponseData, Error>> {
         let (tx, rx) = tokio::sync::oneshot::channel();
         {
             let mut state = self.state.lock().unwrap();
-            state.buffered_commands.push(command);
-            state.buffered_tx.push(tx);
+            let batch = state
+                .buffered
+                .entry(reference_node_id)
+                .or_insert_with(|| Batch {
+                    commands: Vec::with_capacity(4),
+                    tx: Vec::with_capacity(4),
+                });
+            batch.commands.push(command);
+            batch.tx.push(tx);
         }
         rx
     }
 
     /// Send all buffered commands
     pub fn send_commands(&self) {
-        let (commands, tx) = {
+        let buffered = {
             let mut state = self.state.lock().unwrap();
-            if state.buffered_commands.is_empty() {
+            if state.buffered.is_empty() {
                 return;
             }
 
-            let mut commands = Vec::with_capacity(4);
-            std::mem::swap(&mut commands, &mut state.buffered_commands);
-
-            let mut tx = Vec::with_capacity(4);
-            std::mem::swap(&mut tx, &mut state.buffered_tx);
-
-            (commands, tx)
+            std::mem::take(&mut state.buffered)
         };
 
-        let self_clone = self.clone();
-        tokio::spawn(async move {
-            let response = self_clone
-                .client
-                .execute_commands(&commands, None)
-                .await
-                .map_err(ArcError::new);
-            match response {
-                Ok(mut response) => {
-                    for tx in tx.into_iter().rev() {
-                        // The low-level api client ensures that the number of returned responses matches the number of input commands.
-                        let response = response.pop().unwrap();
-                        let response = response.into_result().map_err(Error::from);
-                        let _ = tx.send(response).is_ok();
+        for (reference_node_id, batch) in buffered {
+            let self_clone = self.clone();
+            tokio::spawn(async move {
+                let response = self_clone
+                    .client
+                    .execute_commands(&batch.commands, reference_node_id.as_deref())
+                    .await
+                    .map_err(ArcError::new);
+                match response {
+                    Ok(mut response) => {
+                        // Responses are in the same order as the commands,
+                        // so popping them pairs each with the last sender.
+                        for tx in batch.tx.into_iter().rev() {
+                            // The low-level api client ensures that the number of returned responses matches the number of input commands.
+                            let response = response.pop().unwrap();
+                            let response = response.into_result().map_err(Error::from);
+                            let _ = tx.send(response).is_ok();
+                        }
                     }
-                }
-                Err(error) => {
-                    for tx in tx {
-                        let _ = tx.send(Err(Error::BatchSend(error.clone()))).is_ok();
+                    Err(error) => {
+                        for tx in batch.tx {
+                            let _ = tx.send(Err(Error::BatchSend(error.clone()))).is_ok();
+                        }
                     }
-                }
-            };
-        });
+                };
+            });
+        }
+    }
+
+    /// Check that the api is reachable and responding.
+    ///
+    /// This sends an empty batch of commands, bypassing the command buffer.
+    pub async fn ping(&self) -> Result<(), Error> {
+        self.client.execute_commands(&[], None).await?;
+        Ok(())
     }
 
     /// Get attributes for a file.
+    ///
+    /// If the download url was requested but not returned, the request is retried after a short delay.
+    /// See `set_download_url_retries`.
     pub fn get_attributes(
         &self,
-        file_id: &str,
-        include_download_url: bool,
+        builder: &GetAttributesBuilder,
     ) -> impl Future<Output = Result<GetAttributesResponse, Error>> {
-        let rx = self.queue_command(Command::GetAttributes {
-            file_id: file_id.to_string(),
-            include_download_url: if include_download_url { Some(1) } else { None },
-        });
+        let rx = self.queue_get_attributes(builder);
+
+        let self_clone = self.clone();
+        let builder = builder.clone();
+        async move {
+            let mut response = rx.await.map_err(|_e| Error::NoResponse)??;
+            let mut retries = 0;
+            loop {
+                let get_attributes_response = match response {
+                    ResponseData::GetAttributes(response) => response,
+                    response => {
+                        return Err(Error::UnexpectedResponseDataType {
+                            expected: "GetAttributes",
+                            got: response.kind_name(),
+                        });
+                    }
+                };
+
+                let max_retries = self_clone.state.lock().unwrap().download_url_retries;
+                if builder.include_download_url
+                    && get_attributes_response.download_url.is_none()
+                    && retries < max_retries
+                {
+                    tokio::time::sleep(DOWNLOAD_URL_RETRY_DELAY).await;
+
+                    // Send the retry alone,
+                    // so that commands queued by the caller in the meantime are not sent early.
+                    let mut responses = self_clone
+                        .client
+                        .execute_commands(
+                            &[builder.to_command()],
+                            builder.reference_node_id.as_deref(),
+                        )
+                        .await?;
+                    // The low-level api client ensures that the number of returned responses matches the number of input commands.
+                    response = responses.pop().unwrap().into_result()?;
+                    retries += 1;
+                    continue;
+                }
+
+                return Ok(get_attributes_response);
+            }
+        }
+    }
+
+    /// Get a url to upload a file of the given size to.
+    pub fn get_upload_url(
+        &self,
+        size: u64,
+    ) -> impl Future<Output = Result<UploadUrlResponse, Error>> {
+        let rx = self.queue_command(Command::GetUploadUrl { size }, None);
 
         async {
             let response = rx.await.map_err(|_e| Error::NoResponse)??;
             let response = match response {
-                ResponseData::GetAttributes(response) => response,
-                _ => {
-                    return Err(Error::UnexpectedResponseDataType);
+                ResponseData::UploadUrl(response) => response,
+                response => {
+                    return Err(Error::UnexpectedResponseDataType {
+                        expected: "UploadUrl",
+                        got: response.kind_name(),
+                    });
                 }
             };
 
@@ -109,11 +213,113 @@ impl Client {
         }
     }
 
+    /// Get the direct download url of a public file.
+    ///
+    /// The url serves the encrypted file, so the caller must still decrypt it with the file key.
+    /// Like `get_attributes`, this is queued until `send_commands` is called.
+    pub fn direct_url(&self, public_file_id: &str) -> impl Future<Output = Result<Url, Error>> {
+        let future = self.get_attributes(
+            GetAttributesBuilder::new()
+                .public_file_id(public_file_id)
+                .include_download_url(true),
+        );
+
+        async { future.await?.download_url.ok_or(Error::MissingDownloadUrl) }
+    }
+
+    /// Check whether a file exists.
+    ///
+    /// A missing file results in `false`, while other errors are returned.
+    /// Like `get_attributes`, this is queued until `send_commands` is called.
+    pub fn exists(
+        &self,
+        builder: &GetAttributesBuilder,
+    ) -> impl Future<Output = Result<bool, Error>> {
+        let future = self.get_attributes(builder);
+
+        async {
+            match future.await {
+                Ok(_) => Ok(true),
+                Err(Error::ApiError(ErrorCode::ENOENT)) => Ok(false),
+                Err(error) => Err(error),
+            }
+        }
+    }
+
+    /// Get the attributes of a file and decode them with its key.
+    ///
+    /// This returns both the raw response and the decoded attributes.
+    /// Like `get_attributes`, this is queued until `send_commands` is called.
+    pub fn attributes_for(
+        &self,
+        builder: &GetAttributesBuilder,
+        file_key: &FileKey,
+    ) -> impl Future<Output = Result<(GetAttributesResponse, FileAttributes), Error>> {
+        let future = self.get_attributes(builder);
+        let key = file_key.key;
+
+        async move {
+            let response = future.await?;
+            let attributes = response.decode_attributes(key)?;
+
+            Ok((response, attributes))
+        }
+    }
+
+    /// Queue a get attributes command
+    fn queue_get_attributes(
+        &self,
+        builder: &GetAttributesBuilder,
+    ) -> tokio::sync::oneshot::Receiver<Result<ResponseData, Error>> {
+        self.queue_command(builder.to_command(), builder.reference_node_id.clone())
+    }
+
+    /// Set how many times get_attributes retries when a requested download url is missing.
+    ///
+    /// The storage node for a file is occasionally not ready, in which case the download url is omitted.
+    /// Defaults to 1.
+    pub fn set_download_url_retries(&self, retries: u32) {
+        self.state.lock().unwrap().download_url_retries = retries;
+    }
+
     /// Get the nodes for a folder node.
     ///
+    /// `Some(id)` gets the nodes of the public folder with the given id.
+    /// `None` gets the whole account tree, including the root, inbox, and trash bin.
+    /// This requires a logged in session, which is not supported yet.
+    ///
     /// This bypasses the command buffering system as it is more efficient for Mega's servers to process this alone.
     pub async fn fetch_nodes(&self, node_id: Option<&str>) -> Result<FetchNodesResponse, Error> {
-        let command = Command::FetchNodes { c: 1, r: 1 };
+        self.fetch_nodes_inner(node_id, true).await
+    }
+
+    /// Get the nodes for a folder node, with control over recursion.
+    async fn fetch_nodes_inner(
+        &self,
+        node_id: Option<&str>,
+        recursive: bool,
+    ) -> Result<FetchNodesResponse, Error> {
+        let cache_key = (node_id.map(String::from), recursive);
+        let cache_ttl = {
+            let mut state = self.state.lock().unwrap();
+            match state.fetch_nodes_cache_ttl {
+                Some(ttl) => {
+                    state
+                        .fetch_nodes_cache
+                        .retain(|_, (fetched, _)| fetched.elapsed() < ttl);
+                    if let Some((_, response)) = state.fetch_nodes_cache.get(&cache_key) {
+                        return Ok(response.clone());
+                    }
+                    Some(ttl)
+                }
+                None => None,
+            }
+        };
+
+        let command = Command::FetchNodes {
+            c: 1,
+            r: u8::from(recursive),
+        };
         let mut response = self
             .client
             .execute_commands(std::slice::from_ref(&command), node_id)
@@ -124,26 +330,198 @@ impl Client {
         let response = response.into_result().map_err(Error::from)?;
         let response = match response {
             ResponseData::FetchNodes(response) => response,
-            _ => {
-                return Err(Error::UnexpectedResponseDataType);
+            response => {
+                return Err(Error::UnexpectedResponseDataType {
+                    expected: "FetchNodes",
+                    got: response.kind_name(),
+                });
             }
         };
 
+        if cache_ttl.is_some() {
+            let mut state = self.state.lock().unwrap();
+            state
+                .fetch_nodes_cache
+                .insert(cache_key, (Instant::now(), response.clone()));
+        }
+
         Ok(response)
     }
+
+    /// Set how long fetch_nodes responses are cached.
+    ///
+    /// Repeated fetches of the same folder within this duration reuse the earlier response.
+    /// Pass None to disable caching, which is the default.
+    pub fn set_fetch_nodes_cache_ttl(&self, ttl: Option<Duration>) {
+        let mut state = self.state.lock().unwrap();
+        state.fetch_nodes_cache_ttl = ttl;
+        if ttl.is_none() {
+            state.fetch_nodes_cache.clear();
+        }
+    }
+
+    /// Clear all cached responses
+    pub fn clear_cache(&self) {
+        self.state.lock().unwrap().fetch_nodes_cache.clear();
+    }
+
+    /// List the nodes of a public folder, decrypting their keys and decoding their attributes.
+    ///
+    /// The returned nodes include the folder itself.
+    /// The policy controls what happens when a node fails to decode.
+    pub async fn list_folder(
+        &self,
+        folder_id: &str,
+        folder_key: &FolderKey,
+        recursive: bool,
+        policy: DecodePolicy,
+    ) -> Result<ListFolder, Error> {
+        let response = self.fetch_nodes_inner(Some(folder_id), recursive).await?;
+
+        let mut list = ListFolder {
+            nodes: Vec::with_capacity(response.nodes.len()),
+            errors: Vec::new(),
+        };
+        for node in response.nodes.iter() {
+            match node.decode(folder_key) {
+                Ok(decoded) => list.nodes.push(decoded),
+                Err(error) => match policy {
+                    DecodePolicy::FailFast => return Err(error.into()),
+                    DecodePolicy::Collect => list.errors.push((node.id.clone(), error)),
+                },
+            }
+        }
+
+        Ok(list)
+    }
 }
 
+#[cfg(feature = "reqwest")]
 impl Default for Client {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// What to do when a node fails to decode while listing a folder
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DecodePolicy {
+    /// Abort on the first node that fails to decode.
+    #[default]
+    FailFast,
+
+    /// Skip nodes that fail to decode, collecting their errors.
+    Collect,
+}
+
+/// The result of a list_folder call
+#[derive(Debug)]
+pub struct ListFolder {
+    /// The decoded nodes
+    pub nodes: Vec<DecodedNode>,
+
+    /// The ids of nodes that failed to decode, along with their errors.
+    ///
+    /// This is always empty when using [`DecodePolicy::FailFast`].
+    pub errors: Vec<(String, DecodeAttributesError)>,
+}
+
+/// A builder for a get_attributes call
+///
+/// Each option maps to a field of the `g` command, or to a query parameter of the request.
+#[derive(Debug, Default, Clone)]
+pub struct GetAttributesBuilder {
+    /// The public id of the file.
+    ///
+    /// This is sent as the `p` field.
+    pub public_file_id: Option<String>,
+
+    /// The id of the node.
+    ///
+    /// This is sent as the `n` field.
+    pub node_id: Option<String>,
+
+    /// Whether to include the download url.
+    ///
+    /// This is sent as the `g` field, and the url is returned in the `g` field of the response.
+    pub include_download_url: bool,
+
+    /// The reference node id.
+    ///
+    /// This must be the id of the public folder when requesting a node inside of it.
+    /// This is sent as the `n` query parameter, and applies to the whole batch of commands.
+    pub reference_node_id: Option<String>,
+}
+
+impl GetAttributesBuilder {
+    /// Make a new builder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make a new builder for a node inside of a public folder.
+    ///
+    /// This sets both the node id and the reference node id.
+    pub fn for_folder_child(folder_id: &str, node_id: &str) -> Self {
+        let mut builder = Self::new();
+        builder.node_id(node_id).reference_node_id(folder_id);
+        builder
+    }
+
+    /// Set the public file id
+    pub fn public_file_id(&mut self, public_file_id: &str) -> &mut Self {
+        self.public_file_id = Some(public_file_id.into());
+        self
+    }
+
+    /// Set the node id
+    pub fn node_id(&mut self, node_id: &str) -> &mut Self {
+        self.node_id = Some(node_id.into());
+        self
+    }
+
+    /// Set whether the download url should be included
+    pub fn include_download_url(&mut self, include_download_url: bool) -> &mut Self {
+        self.include_download_url = include_download_url;
+        self
+    }
+
+    /// Set the reference node id
+    pub fn reference_node_id(&mut self, reference_node_id: &str) -> &mut Self {
+        self.reference_node_id = Some(reference_node_id.into());
+        self
+    }
+
+    /// Make the get attributes command for this builder
+    fn to_command(&self) -> Command {
+        Command::GetAttributes {
+            public_file_id: self.public_file_id.clone(),
+            node_id: self.node_id.clone(),
+            include_download_url: if self.include_download_url {
+                Some(1)
+            } else {
+                None
+            },
+        }
+    }
+}
+
 /// The client state
 #[derive(Debug)]
 struct State {
-    buffered_commands: Vec<Command>,
-    buffered_tx: Vec<tokio::sync::oneshot::Sender<Result<ResponseData, Error>>>,
+    buffered: HashMap<Option<String>, Batch>,
+
+    download_url_retries: u32,
+
+    fetch_nodes_cache_ttl: Option<Duration>,
+    fetch_nodes_cache: HashMap<(Option<String>, bool), (Instant, FetchNodesResponse)>,
+}
+
+/// A batch of commands that share a reference node id
+#[derive(Debug)]
+struct Batch {
+    commands: Vec<Command>,
+    tx: Vec<tokio::sync::oneshot::Sender<Result<ResponseData, Error>>>,
 }
 
 /// An error that is wrapped in an Arc
@@ -204,13 +582,19 @@ impl<E> Clone for ArcError<E> {
 mod test {
     use super::*;
     use crate::test::*;
-    use crate::FolderKey;
+    use crate::FetchNodesNodeKind;
 
+    #[cfg(feature = "reqwest")]
     #[tokio::test]
     async fn get_attributes() {
         let client = Client::new();
-        let get_attributes_1_future = client.get_attributes(TEST_FILE_ID, false);
-        let get_attributes_2_future = client.get_attributes(TEST_FILE_ID, true);
+        let get_attributes_1_future =
+            client.get_attributes(GetAttributesBuilder::new().public_file_id(TEST_FILE_ID));
+        let get_attributes_2_future = client.get_attributes(
+            GetAttributesBuilder::new()
+                .public_file_id(TEST_FILE_ID)
+                .include_download_url(true),
+        );
         client.send_commands();
 
         let attributes_1 = get_attributes_1_future
@@ -231,6 +615,7 @@ mod test {
         assert!(file_attributes.name == "Doxygen_docs.zip");
     }
 
+    #[cfg(feature = "reqwest")]
     #[tokio::test]
     async fn fetch_nodes() {
         let folder_key = FolderKey(TEST_FOLDER_KEY_DECODED);
@@ -240,20 +625,388 @@ mod test {
             .fetch_nodes(Some(TEST_FOLDER_ID))
             .await
             .expect("failed to fetch nodes");
-        assert!(response.files.len() == 3);
-        let file_attributes = response.files[0]
+        assert!(response.nodes.len() == 3);
+        let file_attributes = response.nodes[0]
             .decode_attributes(&folder_key)
             .expect("failed to decode attributes");
         assert!(file_attributes.name == "test");
 
-        let file_attributes = dbg!(&response.files[1])
+        let file_attributes = dbg!(&response.nodes[1])
             .decode_attributes(&folder_key)
             .expect("failed to decode attributes");
         assert!(file_attributes.name == "test.txt");
 
-        let file_attributes = dbg!(&response.files[2])
+        let file_attributes = dbg!(&response.nodes[2])
             .decode_attributes(&folder_key)
             .expect("failed to decode attributes");
         assert!(file_attributes.name == "testfolder");
+
+        for node in response.nodes.iter() {
+            let key = node
+                .decrypt_key(&folder_key)
+                .expect("failed to decrypt key");
+            match node.kind {
+                FetchNodesNodeKind::Directory => assert!(key.as_folder_key().is_some()),
+                FetchNodesNodeKind::File => {
+                    let file_key = key.as_file_key().expect("not a file key");
+                    assert!(file_key.to_encoded_bytes().len() == 32);
+                }
+                kind => panic!("unexpected node kind {kind:?}"),
+            }
+        }
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn get_attributes_folder_child() {
+        let folder_key = FolderKey(TEST_FOLDER_KEY_DECODED);
+
+        let client = Client::new();
+        let response = client
+            .fetch_nodes(Some(TEST_FOLDER_ID))
+            .await
+            .expect("failed to fetch nodes");
+        let node = response
+            .nodes
+            .iter()
+            .find(|node| {
+                node.decode_attributes(&folder_key)
+                    .is_ok_and(|attributes| attributes.name == "test.txt")
+            })
+            .expect("missing test.txt");
+
+        let missing_reference_future =
+            client.get_attributes(GetAttributesBuilder::new().node_id(&node.id));
+        let with_reference_future = client.get_attributes(
+            GetAttributesBuilder::for_folder_child(TEST_FOLDER_ID, &node.id)
+                .include_download_url(true),
+        );
+        client.send_commands();
+
+        let error = missing_reference_future
+            .await
+            .expect_err("getting attributes without a reference node id should fail");
+        assert!(
+            matches!(
+                error,
+                Error::ApiError(ErrorCode::EACCESS) | Error::ApiError(ErrorCode::ENOENT)
+            ),
+            "{error:?}"
+        );
+
+        let attributes = with_reference_future
+            .await
+            .expect("failed to get attributes");
+        assert!(Some(attributes.size) == node.size);
+        assert!(attributes.download_url.is_some());
+    }
+
+    #[tokio::test]
+    async fn list_folder() {
+        let (client, transport) = mock_easy_client([TEST_FETCH_NODES_RESPONSE]);
+        let folder_key = FolderKey(TEST_FOLDER_KEY_DECODED);
+
+        let nodes = client
+            .list_folder(TEST_FOLDER_ID, &folder_key, false, DecodePolicy::FailFast)
+            .await
+            .expect("failed to list folder");
+        assert!(nodes.errors.is_empty());
+        let nodes = nodes.nodes;
+        assert!(nodes.len() == 2);
+
+        assert!(nodes[0].id == "AAAAAAAA");
+        assert!(nodes[0].name == "root");
+        assert!(nodes[0].kind == FetchNodesNodeKind::Directory);
+        assert!(nodes[0].key == FolderKey(TEST_FETCH_NODES_DIRECTORY_KEY).into());
+
+        assert!(nodes[1].id == "BBBBBBBB");
+        assert!(nodes[1].parent_id == "AAAAAAAA");
+        assert!(nodes[1].name == "test.txt");
+        assert!(nodes[1].kind == FetchNodesNodeKind::File);
+        assert!(nodes[1].size == Some(1234));
+        let file_key: FileKey = TEST_FILE_KEY.parse().expect("failed to parse file key");
+        assert!(nodes[1].key == file_key.into());
+
+        let requests = transport.requests.lock().unwrap();
+        assert!(&requests[0].1[..] == br#"[{"a":"f","c":1,"r":0}]"#);
+    }
+
+    #[tokio::test]
+    async fn fetch_nodes_cache() {
+        let (client, transport) = mock_easy_client([TEST_FETCH_NODES_RESPONSE; 3]);
+
+        // Caching is disabled by default
+        for _ in 0..2 {
+            client
+                .fetch_nodes(Some(TEST_FOLDER_ID))
+                .await
+                .expect("failed to fetch nodes");
+        }
+        assert!(transport.requests.lock().unwrap().len() == 2);
+
+        client.set_fetch_nodes_cache_ttl(Some(Duration::from_secs(60)));
+        for _ in 0..2 {
+            let response = client
+                .fetch_nodes(Some(TEST_FOLDER_ID))
+                .await
+                .expect("failed to fetch nodes");
+            assert!(response.nodes.len() == 2);
+        }
+        assert!(transport.requests.lock().unwrap().len() == 3);
+
+        client.clear_cache();
+        let error = client
+            .fetch_nodes(Some(TEST_FOLDER_ID))
+            .await
+            .expect_err("the cache was not cleared");
+        assert!(matches!(error, Error::Transport(_)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn get_attributes_retries_missing_download_url() {
+        let without_url = format!("[{}]", get_attributes_response(1234, None));
+        let with_url = format!(
+            "[{}]",
+            get_attributes_response(1234, Some("https://example.com/dl/test"))
+        );
+        let (client, transport) = mock_easy_client([without_url.clone(), with_url, without_url]);
+
+        let start = tokio::time::Instant::now();
+        let future = client.get_attributes(
+            GetAttributesBuilder::new()
+                .public_file_id(TEST_FILE_ID)
+                .include_download_url(true),
+        );
+        client.send_commands();
+        let attributes = future.await.expect("failed to get attributes");
+        assert!(attributes.download_url.is_some());
+        assert!(transport.requests.lock().unwrap().len() == 2);
+        assert!(start.elapsed() == DOWNLOAD_URL_RETRY_DELAY);
+
+        client.set_download_url_retries(0);
+        let future = client.get_attributes(
+            GetAttributesBuilder::new()
+                .public_file_id(TEST_FILE_ID)
+                .include_download_url(true),
+        );
+        client.send_commands();
+        let attributes = future.await.expect("failed to get attributes");
+        assert!(attributes.download_url.is_none());
+        assert!(transport.requests.lock().unwrap().len() == 3);
+    }
+
+    #[tokio::test]
+    async fn get_attributes_batch_order() {
+        let (client, transport) = mock_easy_client([format!(
+            "[{},-9,{}]",
+            get_attributes_response(1, None),
+            get_attributes_response(3, None)
+        )]);
+
+        let futures: Vec<_> = ["AAAAAAAA", "BBBBBBBB", "CCCCCCCC"]
+            .into_iter()
+            .map(|id| client.get_attributes(GetAttributesBuilder::new().public_file_id(id)))
+            .collect();
+        client.send_commands();
+
+        let mut results = Vec::new();
+        for future in futures {
+            results.push(future.await);
+        }
+        assert!(matches!(
+            results[0],
+            Ok(GetAttributesResponse { size: 1, .. })
+        ));
+        assert!(matches!(
+            results[1],
+            Err(Error::ApiError(ErrorCode::ENOENT))
+        ));
+        assert!(matches!(
+            results[2],
+            Ok(GetAttributesResponse { size: 3, .. })
+        ));
+        assert!(transport.requests.lock().unwrap().len() == 1);
+    }
+
+    #[tokio::test]
+    async fn list_folder_decode_policy() {
+        let response = TEST_FETCH_NODES_RESPONSE.replace(
+            "ARvdZKqozqiPslHC9hp6g2Q5LZrXDt8cVEXWjXefVLU",
+            "AAAAAAAAAAAAAAAAAAAAAA",
+        );
+        let (client, _) = mock_easy_client([response.clone(), response]);
+        let folder_key = FolderKey(TEST_FOLDER_KEY_DECODED);
+
+        let result = client
+            .list_folder(TEST_FOLDER_ID, &folder_key, false, DecodePolicy::FailFast)
+            .await;
+        assert!(matches!(result, Err(Error::DecodeAttributes(_))));
+
+        let list = client
+            .list_folder(TEST_FOLDER_ID, &folder_key, false, DecodePolicy::Collect)
+            .await
+            .expect("failed to list folder");
+        assert!(list.nodes.len() == 1);
+        assert!(list.nodes[0].id == "AAAAAAAA");
+        assert!(list.errors.len() == 1);
+        assert!(list.errors[0].0 == "BBBBBBBB");
+    }
+
+    #[tokio::test]
+    async fn clone_shares_state() {
+        let (client, transport) = mock_easy_client([r#"[{"p":"https://example.com/ul/test"}]"#]);
+        let cloned = client.clone();
+
+        let future = client.get_upload_url(1234);
+        cloned.send_commands();
+        future.await.expect("failed to get upload url");
+
+        assert!(client.client.sequence_id() == cloned.client.sequence_id());
+        assert!(Arc::ptr_eq(&client.state, &cloned.state));
+        assert!(transport.requests.lock().unwrap().len() == 1);
+    }
+
+    #[tokio::test]
+    async fn unexpected_response_data_type() {
+        let (client, _) = mock_easy_client([r#"[{"f":[],"noc":0,"sn":"sn","st":"st"}]"#]);
+
+        let future = client.get_upload_url(1234);
+        client.send_commands();
+        let error = future.await.expect_err("response type should not match");
+        assert!(matches!(
+            error,
+            Error::UnexpectedResponseDataType {
+                expected: "UploadUrl",
+                got: "FetchNodes",
+            }
+        ));
+        assert!(error.to_string() == "expected a 'UploadUrl' response, but got 'FetchNodes'");
+    }
+
+    #[tokio::test]
+    async fn low_level() {
+        let (client, _) = mock_easy_client([r#"[{"p":"https://example.com/ul/test"}]"#]);
+
+        let response = client
+            .low_level()
+            .execute_commands(&[Command::GetUploadUrl { size: 1234 }], None)
+            .await
+            .expect("failed to execute commands");
+        assert!(matches!(
+            response[0],
+            crate::Response::Ok(ResponseData::UploadUrl(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn direct_url() {
+        let with_url = format!(
+            "[{}]",
+            get_attributes_response(1234, Some("https://example.com/dl/test"))
+        );
+        let without_url = format!("[{}]", get_attributes_response(1234, None));
+        let (client, transport) = mock_easy_client([with_url, without_url]);
+        client.set_download_url_retries(0);
+
+        let future = client.direct_url(TEST_FILE_ID);
+        client.send_commands();
+        let url = future.await.expect("failed to get direct url");
+        assert!(url.as_str() == "https://example.com/dl/test");
+
+        let future = client.direct_url(TEST_FILE_ID);
+        client.send_commands();
+        let error = future.await.expect_err("download url should be missing");
+        assert!(matches!(error, Error::MissingDownloadUrl));
+
+        let requests = transport.requests.lock().unwrap();
+        assert!(
+            &requests[0].1[..] == format!(r#"[{{"a":"g","p":"{TEST_FILE_ID}","g":1}}]"#).as_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn ping() {
+        let (client, transport) = mock_easy_client(["[]", "<html></html>"]);
+
+        client.ping().await.expect("failed to ping");
+        assert!(client.ping().await.is_err());
+        assert!(&transport.requests.lock().unwrap()[0].1[..] == b"[]");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn get_attributes_retry_does_not_send_queued_commands() {
+        let without_url = format!("[{}]", get_attributes_response(1234, None));
+        let with_url = format!(
+            "[{}]",
+            get_attributes_response(1234, Some("https://example.com/dl/test"))
+        );
+        let (client, transport) = mock_easy_client([without_url, with_url]);
+
+        let future = client.get_attributes(
+            GetAttributesBuilder::new()
+                .public_file_id(TEST_FILE_ID)
+                .include_download_url(true),
+        );
+        client.send_commands();
+        let _upload_url_future = client.get_upload_url(1234);
+        let attributes = future.await.expect("failed to get attributes");
+        assert!(attributes.download_url.is_some());
+
+        let requests = transport.requests.lock().unwrap();
+        assert!(requests.len() == 2);
+        assert!(
+            &requests[1].1[..] == format!(r#"[{{"a":"g","p":"{TEST_FILE_ID}","g":1}}]"#).as_bytes()
+        );
+        assert!(client.state.lock().unwrap().buffered.len() == 1);
+    }
+
+    #[tokio::test]
+    async fn exists() {
+        let found = format!("[{}]", get_attributes_response(1234, None));
+        let (client, _) = mock_easy_client([found.as_str(), "[-9]", "[-11]"]);
+        let mut builder = GetAttributesBuilder::new();
+        builder.public_file_id(TEST_FILE_ID);
+
+        for expected in [true, false] {
+            let future = client.exists(&builder);
+            client.send_commands();
+            assert!(future.await.expect("failed to check existence") == expected);
+        }
+
+        let future = client.exists(&builder);
+        client.send_commands();
+        assert!(matches!(
+            future.await,
+            Err(Error::ApiError(ErrorCode::EACCESS))
+        ));
+    }
+
+    #[tokio::test]
+    async fn attributes_for() {
+        let response = format!("[{}]", get_attributes_response(1234, None));
+        let (client, _) = mock_easy_client([response]);
+        let file_key: FileKey = TEST_FILE_KEY.parse().expect("failed to parse file key");
+
+        let future = client.attributes_for(
+            GetAttributesBuilder::new().public_file_id(TEST_FILE_ID),
+            &file_key,
+        );
+        client.send_commands();
+        let (response, attributes) = future.await.expect("failed to get attributes");
+        assert!(response.size == 1234);
+        assert!(attributes.name == "Doxygen_docs.zip");
+    }
+
+    #[tokio::test]
+    async fn get_upload_url() {
+        let (client, transport) = mock_easy_client([r#"[{"p":"https://example.com/ul/test"}]"#]);
+
+        let future = client.get_upload_url(1234);
+        client.send_commands();
+        let response = future.await.expect("failed to get upload url");
+        assert!(response.upload_url.as_str() == "https://example.com/ul/test");
+
+        let requests = transport.requests.lock().unwrap();
+        assert!(&requests[0].1[..] == br#"[{"a":"u","s":1234}]"#);
     }
 }
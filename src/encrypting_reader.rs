@@ -0,0 +1,208 @@
+use crate::mac::MetaMacCalculator;
+use crate::FileKey;
+use cbc::cipher::KeyIvInit;
+use cbc::cipher::StreamCipher;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use tokio::io::AsyncRead;
+use tokio::io::ReadBuf;
+
+type Aes128Ctr128BE = ctr::Ctr128BE<aes::Aes128>;
+
+/// A reader that encrypts a plaintext file for upload.
+///
+/// This applies AES-CTR with the file key and computes the meta mac of the plaintext as it is read.
+/// The meta mac is only complete once the inner reader reaches EOF.
+pub struct EncryptingReader<R> {
+    /// The plaintext reader
+    reader: R,
+
+    /// The file key
+    file_key: FileKey,
+
+    /// The cipher
+    cipher: Aes128Ctr128BE,
+
+    /// The meta mac calculator
+    mac: MetaMacCalculator,
+}
+
+impl<R> EncryptingReader<R> {
+    /// Make a new EncryptingReader.
+    ///
+    /// The meta mac of the given key is ignored.
+    pub fn new(reader: R, file_key: FileKey) -> Self {
+        let cipher = Aes128Ctr128BE::new(
+            &file_key.key.to_ne_bytes().into(),
+            &file_key.iv.to_ne_bytes().into(),
+        );
+        let mac = MetaMacCalculator::new(&file_key);
+
+        Self {
+            reader,
+            file_key,
+            cipher,
+            mac,
+        }
+    }
+
+    /// Get the meta mac of the plaintext read so far
+    pub fn meta_mac(&self) -> u64 {
+        self.mac.finish()
+    }
+
+    /// Get the file key, with the meta mac of the plaintext read so far
+    pub fn file_key(&self) -> FileKey {
+        FileKey {
+            meta_mac: self.meta_mac(),
+            ..self.file_key.clone()
+        }
+    }
+
+    /// Get the inner reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R> std::fmt::Debug for EncryptingReader<R>
+where
+    R: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Avoid printing key material
+        f.debug_struct("EncryptingReader")
+            .field("reader", &self.reader)
+            .field("mac", &self.mac)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R> AsyncRead for EncryptingReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+
+        let start = buf.filled().len();
+        match Pin::new(&mut this.reader).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {}
+            poll => return poll,
+        }
+
+        let data = &mut buf.filled_mut()[start..];
+        this.mac.update(data);
+        this.cipher.apply_keystream(data);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn encrypt_decrypt_round_trip() {
+        let file_key = FileKey {
+            key: TEST_FILE_KEY_KEY_DECODED,
+            iv: TEST_FILE_KEY_IV_DECODED,
+            meta_mac: 0,
+        };
+
+        let mut reader = EncryptingReader::new(TEST_FILE_BYTES, file_key.clone());
+        let mut encrypted = Vec::new();
+        reader
+            .read_to_end(&mut encrypted)
+            .await
+            .expect("failed to read");
+        assert!(encrypted.len() == TEST_FILE_BYTES.len());
+        assert!(encrypted != TEST_FILE_BYTES);
+        assert!(reader.meta_mac() == TEST_FILE_META_MAC_DECODED);
+
+        let mut cipher = Aes128Ctr128BE::new(
+            &file_key.key.to_ne_bytes().into(),
+            &file_key.iv.to_ne_bytes().into(),
+        );
+        cipher.apply_keystream(&mut encrypted);
+        assert!(encrypted == TEST_FILE_BYTES);
+    }
+
+    #[tokio::test]
+    async fn generated_key_round_trip() {
+        let mut reader = EncryptingReader::new(TEST_FILE_BYTES, FileKey::generate());
+        let mut encrypted = Vec::new();
+        reader
+            .read_to_end(&mut encrypted)
+            .await
+            .expect("failed to read");
+        let file_key = reader.file_key();
+
+        let mut reader = EncryptingReader::new(TEST_FILE_BYTES, file_key.clone());
+        let mut encrypted_again = Vec::new();
+        reader
+            .read_to_end(&mut encrypted_again)
+            .await
+            .expect("failed to read");
+        assert!(encrypted == encrypted_again);
+        assert!(reader.file_key() == file_key);
+    }
+
+    #[tokio::test]
+    async fn empty_file() {
+        let file_key = FileKey {
+            key: TEST_FILE_KEY_KEY_DECODED,
+            iv: TEST_FILE_KEY_IV_DECODED,
+            meta_mac: 0,
+        };
+
+        let mut reader = EncryptingReader::new(&[][..], file_key.clone());
+        let mut encrypted = Vec::new();
+        reader
+            .read_to_end(&mut encrypted)
+            .await
+            .expect("failed to read");
+        assert!(encrypted.is_empty());
+
+        // Round trip the derived key through its link form,
+        // as a downloader would receive it.
+        let derived_key = reader.file_key();
+        let parsed_key: FileKey = derived_key
+            .to_string()
+            .parse()
+            .expect("failed to parse file key");
+        assert!(parsed_key == derived_key);
+        assert!(parsed_key.key == file_key.key);
+        assert!(parsed_key.iv == file_key.iv);
+
+        // Decrypting with the parsed key must give back the empty plaintext,
+        // and re-computing the meta mac must agree with the one in the key.
+        let mut cipher = Aes128Ctr128BE::new(
+            &parsed_key.key.to_ne_bytes().into(),
+            &parsed_key.iv.to_ne_bytes().into(),
+        );
+        cipher.apply_keystream(&mut encrypted);
+        assert!(encrypted.is_empty());
+
+        let mut reader = EncryptingReader::new(&encrypted[..], parsed_key.clone());
+        let mut encrypted_again = Vec::new();
+        reader
+            .read_to_end(&mut encrypted_again)
+            .await
+            .expect("failed to read");
+        assert!(encrypted_again.is_empty());
+        assert!(reader.file_key() == parsed_key);
+
+        // The same key over non-empty data must give a different meta mac,
+        // so the empty file's meta mac is not a default that every input shares.
+        assert!(parsed_key.meta_mac != TEST_FILE_META_MAC_DECODED);
+    }
+}
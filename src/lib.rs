@@ -1,33 +1,116 @@
+#[cfg(feature = "serde")]
 mod client;
 #[cfg(feature = "easy")]
 mod easy;
+mod encrypting_reader;
+mod mac;
+/// Commonly used types, for `use mega::prelude::*;`
+pub mod prelude;
+#[cfg(feature = "serde")]
+mod transport;
 mod types;
 
+#[cfg(feature = "serde")]
 pub use self::client::Client;
+#[cfg(feature = "serde")]
+pub use self::client::API_HOST;
 #[cfg(feature = "easy")]
 pub use self::easy::Client as EasyClient;
+#[cfg(feature = "easy")]
+pub use self::easy::DecodePolicy;
+#[cfg(feature = "easy")]
+pub use self::easy::GetAttributesBuilder;
+#[cfg(feature = "easy")]
+pub use self::easy::ListFolder;
+pub use self::encrypting_reader::EncryptingReader;
+#[cfg(feature = "serde")]
+pub use self::transport::Transport;
+#[cfg(feature = "serde")]
+pub use self::transport::TransportFuture;
+#[cfg(feature = "serde")]
 pub use self::types::Command;
+#[cfg(feature = "serde")]
+pub use self::types::DecodeAttributesError;
+#[cfg(feature = "serde")]
+pub use self::types::DecodedNode;
 pub use self::types::ErrorCode;
+#[cfg(feature = "serde")]
+pub use self::types::FetchNodesNode;
+#[cfg(feature = "serde")]
+pub use self::types::FetchNodesNodeKind;
+#[cfg(feature = "serde")]
 pub use self::types::FetchNodesResponse;
+#[cfg(feature = "serde")]
+pub use self::types::FileAttributes;
 pub use self::types::FileKey;
 pub use self::types::FileKeyParseError;
+pub use self::types::FileOrFolderKey;
 pub use self::types::FolderKey;
 pub use self::types::FolderKeyParseError;
+#[cfg(feature = "serde")]
 pub use self::types::GetAttributesResponse;
+pub use self::types::KeyKind;
+#[cfg(feature = "serde")]
 pub use self::types::Response;
+#[cfg(feature = "serde")]
 pub use self::types::ResponseData;
+#[cfg(feature = "serde")]
+pub use self::types::UploadCompletionToken;
+#[cfg(feature = "serde")]
+pub use self::types::UploadUrlResponse;
+#[cfg(feature = "serde")]
+pub use self::types::DEFAULT_MAX_ENCODED_ATTRIBUTES_LEN;
 
 /// The library error type
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     /// A reqwest Error
+    ///
+    /// Timeouts and http status errors are reported as `Timeout` and `Http` instead.
+    #[cfg(feature = "reqwest")]
+    #[error(transparent)]
+    Reqwest(reqwest::Error),
+
+    /// The request timed out
+    #[error("request timed out")]
+    Timeout,
+
+    /// The server responded with a non-success http status
+    #[error("http status '{status}'")]
+    Http { status: u16 },
+
+    /// An error from a custom transport
+    #[error("transport error")]
+    Transport(#[source] Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    /// A Json Error
+    #[cfg(feature = "serde")]
     #[error(transparent)]
-    Reqwest(#[from] reqwest::Error),
+    Json(#[from] serde_json::Error),
 
     /// A Url Error
     #[error(transparent)]
     Url(#[from] url::ParseError),
 
+    /// Failed to parse a file key
+    #[error("failed to parse file key")]
+    FileKeyParse(#[from] FileKeyParseError),
+
+    /// Failed to parse a folder key
+    #[error("failed to parse folder key")]
+    FolderKeyParse(#[from] FolderKeyParseError),
+
+    /// The response body was not json, like an html error page
+    #[error("unexpected response body '{snippet}'")]
+    UnexpectedResponseBody { snippet: String },
+
+    /// A response contained fields that were not recognized.
+    ///
+    /// This is only returned when unknown fields are denied.
+    #[cfg(feature = "serde")]
+    #[error("unknown response fields {fields:?}")]
+    UnknownFields { fields: Vec<String> },
+
     /// The returned number of responses did not match what was expected
     #[error("expected '{expected}' responses, but got '{actual}'")]
     ResponseLengthMismatch { expected: usize, actual: usize },
@@ -36,6 +119,11 @@ pub enum Error {
     #[error("api error")]
     ApiError(#[from] ErrorCode),
 
+    /// Failed to decode attributes
+    #[cfg(feature = "serde")]
+    #[error("failed to decode attributes")]
+    DecodeAttributes(#[from] DecodeAttributesError),
+
     #[cfg(feature = "easy")]
     #[error("channel closed without response")]
     NoResponse,
@@ -45,25 +133,84 @@ pub enum Error {
     BatchSend(self::easy::ArcError<Self>),
 
     #[cfg(feature = "easy")]
-    #[error("unexpected response data type")]
-    UnexpectedResponseDataType,
+    #[error("the download url was not returned")]
+    MissingDownloadUrl,
+
+    #[cfg(feature = "easy")]
+    #[error("expected a '{expected}' response, but got '{got}'")]
+    UnexpectedResponseDataType {
+        expected: &'static str,
+        got: &'static str,
+    },
+}
+
+#[cfg(feature = "reqwest")]
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            return Self::Timeout;
+        }
+
+        match error.status() {
+            Some(status) => Self::Http {
+                status: status.as_u16(),
+            },
+            None => Self::Reqwest(error),
+        }
+    }
+}
+
+/// The hosts that serve Mega links
+const MEGA_HOSTS: &[&str] = &["mega.nz", "mega.co.nz"];
+
+/// Check whether a url is a Mega file or folder link.
+///
+/// This only checks the host and path, and does not validate the id or key.
+pub fn is_mega_url(url: &url::Url) -> bool {
+    let host = match url.host_str() {
+        Some(host) => host,
+        None => return false,
+    };
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    if !MEGA_HOSTS.contains(&host) {
+        return false;
+    }
+
+    matches!(
+        url.path_segments().and_then(|mut segments| segments.next()),
+        Some("file" | "folder")
+    )
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    #[cfg(feature = "serde")]
+    use bytes::Bytes;
+    #[cfg(feature = "reqwest")]
     use cbc::cipher::KeyIvInit;
+    #[cfg(feature = "reqwest")]
     use cbc::cipher::StreamCipher;
+    #[cfg(feature = "serde")]
+    use std::collections::VecDeque;
+    #[cfg(feature = "serde")]
+    use std::sync::Arc;
+    #[cfg(feature = "serde")]
+    use std::sync::Mutex;
+    use url::Url;
 
+    #[cfg(feature = "reqwest")]
     type Aes128Ctr128BE = ctr::Ctr128BE<aes::Aes128>;
 
     // const TEST_FILE: &str =
     //    "https://mega.nz/file/7glwEQBT#Fy9cwPpCmuaVdEkW19qwBLaiMeyufB1kseqisOAxfi8";
     pub const TEST_FILE_KEY: &str = "Fy9cwPpCmuaVdEkW19qwBLaiMeyufB1kseqisOAxfi8";
+    #[cfg(feature = "serde")]
     pub const TEST_FILE_ID: &str = "7glwEQBT";
 
     // const TEST_FOLDER: &str = "https://mega.nz/folder/MWsm3aBL#xsXXTpoYEFDRQdeHPDrv7A";
     pub const TEST_FOLDER_KEY: &str = "xsXXTpoYEFDRQdeHPDrv7A";
+    #[cfg(feature = "serde")]
     pub const TEST_FOLDER_ID: &str = "MWsm3aBL";
 
     pub const TEST_FILE_KEY_KEY_DECODED: u128 = u128::from_ne_bytes([
@@ -77,7 +224,88 @@ mod test {
         198, 197, 215, 78, 154, 24, 16, 80, 209, 65, 215, 135, 60, 58, 239, 236,
     ]);
 
-    const TEST_FILE_BYTES: &[u8] = include_bytes!("../test_data/Doxygen_docs.zip");
+    pub const TEST_FILE_BYTES: &[u8] = include_bytes!("../test_data/Doxygen_docs.zip");
+
+    #[cfg(feature = "serde")]
+    /// The encoded attributes of the test file
+    pub const TEST_FILE_ENCODED_ATTRIBUTES: &str = "onUPaguLcBplq_ny9wnYVg87e9blv8ANPGoZ3OBUli0";
+
+    #[cfg(feature = "serde")]
+    /// A FetchNodes response for a synthetic folder, encrypted with the test folder key.
+    ///
+    /// This contains a directory named "root" with the id "AAAAAAAA",
+    /// and a file named "test.txt" with the id "BBBBBBBB" that uses the test file key.
+    pub const TEST_FETCH_NODES_RESPONSE: &str = r#"[{"f":[{"h":"AAAAAAAA","p":"","u":"UUUUUUUUUUU","t":1,"a":"9Ka8KGO-FbIto5gz159p1A","k":"AAAAAAAA:scxbPwzgRbyLF6h9hnfn0w","ts":0},{"h":"BBBBBBBB","p":"AAAAAAAA","u":"UUUUUUUUUUU","t":0,"a":"ARvdZKqozqiPslHC9hp6g2Q5LZrXDt8cVEXWjXefVLU","k":"AAAAAAAA:qkjN4oJ9RJu47cEyI8YcxHrOGacfNmVx3JKylRCvFCI","s":1234,"ts":0}],"noc":1,"sn":"sn","st":"st"}]"#;
+
+    #[cfg(feature = "serde")]
+    /// The key of the "root" directory in the synthetic folder
+    pub const TEST_FETCH_NODES_DIRECTORY_KEY: u128 =
+        u128::from_ne_bytes([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+
+    #[cfg(feature = "serde")]
+    /// A transport that serves canned responses instead of contacting the Mega api.
+    #[derive(Debug, Clone, Default)]
+    pub struct MockTransport {
+        /// The response bodies to serve, in order
+        pub responses: Arc<Mutex<VecDeque<String>>>,
+
+        /// The requests that were sent
+        pub requests: Arc<Mutex<Vec<(Url, Bytes)>>>,
+    }
+
+    #[cfg(feature = "serde")]
+    impl MockTransport {
+        /// Make a new MockTransport that serves the given response bodies
+        pub fn new<I>(responses: I) -> Self
+        where
+            I: IntoIterator,
+            I::Item: Into<String>,
+        {
+            Self {
+                responses: Arc::new(Mutex::new(responses.into_iter().map(Into::into).collect())),
+                requests: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    impl Transport for MockTransport {
+        fn post_json<'a>(&'a self, url: &'a Url, body: Bytes) -> TransportFuture<'a> {
+            self.requests.lock().unwrap().push((url.clone(), body));
+            let response = self
+                .responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .map(Bytes::from)
+                .ok_or_else(|| Error::Transport("no response queued".into()));
+
+            Box::pin(async move { response })
+        }
+    }
+
+    /// Make a GetAttributes response object for the test file, with the given size and download url
+    #[cfg(feature = "serde")]
+    pub fn get_attributes_response(size: u64, download_url: Option<&str>) -> String {
+        match download_url {
+            Some(download_url) => format!(
+                r#"{{"s":{size},"at":"{TEST_FILE_ENCODED_ATTRIBUTES}","msd":1,"g":"{download_url}"}}"#
+            ),
+            None => format!(r#"{{"s":{size},"at":"{TEST_FILE_ENCODED_ATTRIBUTES}","msd":1}}"#),
+        }
+    }
+
+    /// Make an easy client that uses a MockTransport serving the given response bodies
+    #[cfg(feature = "easy")]
+    pub fn mock_easy_client<I>(responses: I) -> (EasyClient, MockTransport)
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let transport = MockTransport::new(responses);
+        let client = EasyClient::from_client(Client::with_transport(transport.clone()));
+        (client, transport)
+    }
 
     #[test]
     fn parse_file_key() {
@@ -87,12 +315,573 @@ mod test {
         assert!(file_key.meta_mac == TEST_FILE_META_MAC_DECODED);
     }
 
+    #[test]
+    fn parse_file_key_byte_order() {
+        // These are compared as big-endian integers of the in-memory bytes,
+        // so they hold on hosts of any endianness.
+        let file_key: FileKey = TEST_FILE_KEY.parse().expect("failed to parse file key");
+        assert!(
+            u128::from_be_bytes(file_key.key.to_ne_bytes())
+                == 0xa18d6d2c_543e8782_249eeba6_37ebce2b
+        );
+        assert!(
+            u128::from_be_bytes(file_key.iv.to_ne_bytes()) == 0xb6a231ec_ae7c1d64_00000000_00000000
+        );
+        assert!(u64::from_be_bytes(file_key.meta_mac.to_ne_bytes()) == 0xb1eaa2b0_e0317e2f);
+
+        let folder_key: FolderKey = TEST_FOLDER_KEY.parse().expect("failed to parse folder key");
+        assert!(
+            u128::from_be_bytes(folder_key.0.to_ne_bytes())
+                == 0xc6c5d74e_9a181050_d141d787_3c3aefec
+        );
+    }
+
     #[test]
     fn parse_folder_key() {
         let folder_key: FolderKey = TEST_FOLDER_KEY.parse().expect("failed to parse folder key");
         assert!(folder_key.0 == TEST_FOLDER_KEY_DECODED);
     }
 
+    #[test]
+    fn parse_full_folder_key() {
+        let folder_key: FolderKey = TEST_FILE_KEY.parse().expect("failed to parse folder key");
+        let file_key: FileKey = TEST_FILE_KEY.parse().expect("failed to parse file key");
+        assert!(folder_key.0 == file_key.key);
+
+        let too_long = format!("{TEST_FILE_KEY}A");
+        assert!(matches!(
+            too_long.parse::<FolderKey>(),
+            Err(FolderKeyParseError::InvalidBase64Length { length: 44 })
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn key_try_from() {
+        let file_key = FileKey::try_from(TEST_FILE_KEY).expect("failed to parse file key");
+        let parsed =
+            FileKey::try_from(String::from(file_key.clone())).expect("failed to parse file key");
+        assert!(parsed == file_key);
+        let json = serde_json::to_string(&file_key).expect("failed to serialize file key");
+        assert!(json == format!("\"{TEST_FILE_KEY}\""));
+        let parsed: FileKey = serde_json::from_str(&json).expect("failed to deserialize file key");
+        assert!(parsed == file_key);
+
+        let folder_key = FolderKey::try_from(TEST_FOLDER_KEY).expect("failed to parse folder key");
+        let parsed =
+            FolderKey::try_from(String::from(folder_key)).expect("failed to parse folder key");
+        assert!(parsed == folder_key);
+        let json = serde_json::to_string(&folder_key).expect("failed to serialize folder key");
+        assert!(json == format!("\"{TEST_FOLDER_KEY}\""));
+        let parsed: FolderKey =
+            serde_json::from_str(&json).expect("failed to deserialize folder key");
+        assert!(parsed == folder_key);
+
+        assert!(serde_json::from_str::<FolderKey>("\"AAAA\"").is_err());
+    }
+
+    #[test]
+    fn mega_url() {
+        for url in [
+            "https://mega.nz/file/7glwEQBT#Fy9cwPpCmuaVdEkW19qwBLaiMeyufB1kseqisOAxfi8",
+            "https://mega.nz/folder/MWsm3aBL#xsXXTpoYEFDRQdeHPDrv7A",
+            "https://www.mega.co.nz/folder/MWsm3aBL#xsXXTpoYEFDRQdeHPDrv7A",
+        ] {
+            let url = Url::parse(url).expect("failed to parse url");
+            assert!(is_mega_url(&url));
+        }
+
+        for url in [
+            "https://mega.nz/",
+            "https://mega.nz/login",
+            "https://example.com/file/7glwEQBT",
+            "https://notmega.nz/folder/MWsm3aBL",
+        ] {
+            let url = Url::parse(url).expect("failed to parse url");
+            assert!(!is_mega_url(&url));
+        }
+    }
+
+    #[test]
+    fn file_key_to_string() {
+        let file_key: FileKey = TEST_FILE_KEY.parse().expect("failed to parse file key");
+        assert!(file_key.to_string() == TEST_FILE_KEY);
+    }
+
+    #[test]
+    fn folder_key_to_string() {
+        let folder_key: FolderKey = TEST_FOLDER_KEY.parse().expect("failed to parse folder key");
+        assert!(folder_key.to_string() == TEST_FOLDER_KEY);
+    }
+
+    #[cfg(feature = "subtle")]
+    #[test]
+    fn constant_time_eq() {
+        use subtle::ConstantTimeEq;
+
+        let file_key: FileKey = TEST_FILE_KEY.parse().expect("failed to parse file key");
+        assert!(bool::from(file_key.ct_eq(&file_key.clone())));
+        let other = FileKey {
+            meta_mac: 0,
+            ..file_key.clone()
+        };
+        assert!(!bool::from(file_key.ct_eq(&other)));
+
+        let folder_key: FolderKey = TEST_FOLDER_KEY.parse().expect("failed to parse folder key");
+        assert!(bool::from(folder_key.ct_eq(&folder_key)));
+        assert!(!bool::from(folder_key.ct_eq(&FolderKey(0))));
+    }
+
+    #[test]
+    fn generate_file_key() {
+        let file_key = FileKey::generate();
+        assert!(file_key.meta_mac == 0);
+        assert!(file_key.iv.to_ne_bytes()[8..] == [0; 8]);
+
+        let parsed: FileKey = file_key
+            .to_string()
+            .parse()
+            .expect("failed to parse file key");
+        assert!(parsed == file_key);
+    }
+
+    #[test]
+    fn generate_folder_key() {
+        let folder_key = FolderKey::generate();
+        let parsed: FolderKey = folder_key
+            .to_string()
+            .parse()
+            .expect("failed to parse folder key");
+        assert!(parsed == folder_key);
+    }
+
+    #[test]
+    fn generate_file_or_folder_key() {
+        let file_key = FileOrFolderKey::generate(KeyKind::File);
+        assert!(file_key.kind() == KeyKind::File);
+        assert!(file_key.is_file_key());
+        assert!(!file_key.is_folder_key());
+
+        let folder_key = FileOrFolderKey::generate_folder();
+        assert!(folder_key.kind() == KeyKind::Folder);
+        assert!(folder_key.is_folder_key());
+        assert!(!folder_key.is_file_key());
+    }
+
+    #[cfg(feature = "serde")]
+    /// Make a FetchNodesNode with the given id, parent id, and kind
+    pub fn make_node(id: &str, parent_id: &str, kind: FetchNodesNodeKind) -> FetchNodesNode {
+        serde_json::from_value(serde_json::json!({
+            "a": "",
+            "h": id,
+            "k": "",
+            "p": parent_id,
+            "t": kind,
+            "ts": 0,
+            "u": "",
+        }))
+        .expect("failed to make node")
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn node_public_url() {
+        let folder_key = FolderKey(TEST_FOLDER_KEY_DECODED);
+
+        let file = make_node("AAAAAAAA", TEST_FOLDER_ID, FetchNodesNodeKind::File);
+        assert!(
+            file.public_url(TEST_FOLDER_ID, &folder_key).as_deref()
+                == Some("https://mega.nz/folder/MWsm3aBL#xsXXTpoYEFDRQdeHPDrv7A/file/AAAAAAAA")
+        );
+
+        let directory = make_node("BBBBBBBB", TEST_FOLDER_ID, FetchNodesNodeKind::Directory);
+        assert!(
+            directory.public_url(TEST_FOLDER_ID, &folder_key).as_deref()
+                == Some("https://mega.nz/folder/MWsm3aBL#xsXXTpoYEFDRQdeHPDrv7A/folder/BBBBBBBB")
+        );
+
+        for kind in [
+            FetchNodesNodeKind::Root,
+            FetchNodesNodeKind::Inbox,
+            FetchNodesNodeKind::TrashBin,
+        ] {
+            let node = make_node("CCCCCCCC", TEST_FOLDER_ID, kind);
+            assert!(node.public_url(TEST_FOLDER_ID, &folder_key).is_none());
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    /// Parse the synthetic FetchNodes response
+    pub fn test_fetch_nodes_response() -> FetchNodesResponse {
+        let mut response: Vec<Response<ResponseData>> =
+            serde_json::from_str(TEST_FETCH_NODES_RESPONSE).expect("failed to parse response");
+        match response.swap_remove(0).into_result() {
+            Ok(ResponseData::FetchNodes(response)) => response,
+            _ => panic!("unexpected response"),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn decode_nodes() {
+        let folder_key = FolderKey(TEST_FOLDER_KEY_DECODED);
+        let response = test_fetch_nodes_response();
+
+        let directory = response.nodes[0]
+            .decode(&folder_key)
+            .expect("failed to decode node");
+        assert!(directory.name == "root");
+        assert!(directory.key == FolderKey(TEST_FETCH_NODES_DIRECTORY_KEY).into());
+
+        let file = response.nodes[1]
+            .decode(&folder_key)
+            .expect("failed to decode node");
+        assert!(file.name == "test.txt");
+        let file_key = file.key.as_file_key().expect("not a file key");
+        assert!(file_key.key == TEST_FILE_KEY_KEY_DECODED);
+        assert!(file_key.iv == TEST_FILE_KEY_IV_DECODED);
+        assert!(file_key.meta_mac == TEST_FILE_META_MAC_DECODED);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn decrypt_node_keys() {
+        let folder_key = FolderKey(TEST_FOLDER_KEY_DECODED);
+        let response = test_fetch_nodes_response();
+
+        let key = response.nodes[0]
+            .decrypt_key(&folder_key)
+            .expect("failed to decrypt key");
+        assert!(key == FileOrFolderKey::Folder(FolderKey(TEST_FETCH_NODES_DIRECTORY_KEY)));
+
+        let key = response.nodes[1]
+            .decrypt_key(&folder_key)
+            .expect("failed to decrypt key");
+        let file_key = key.as_file_key().expect("not a file key");
+        assert!(file_key.to_encoded_bytes().len() == 32);
+
+        // A directory key is too short for a file, and a file key is too long for a directory.
+        let mut directory = response.nodes[0].clone();
+        directory.kind = FetchNodesNodeKind::File;
+        assert!(matches!(
+            directory.decrypt_key(&folder_key),
+            Err(DecodeAttributesError::InvalidKeyLength {
+                length: 16,
+                expected: 32,
+                kind: FetchNodesNodeKind::File,
+            })
+        ));
+
+        let mut file = response.nodes[1].clone();
+        file.kind = FetchNodesNodeKind::Directory;
+        assert!(matches!(
+            file.decrypt_key(&folder_key),
+            Err(DecodeAttributesError::InvalidKeyLength {
+                length: 32,
+                expected: 16,
+                kind: FetchNodesNodeKind::Directory,
+            })
+        ));
+        let error = file
+            .decrypt_key(&folder_key)
+            .expect_err("key length should not match");
+        assert!(
+            error.to_string()
+                == "invalid key length '32' for a Directory node, expected length of '16'"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn decrypt_nested_share_keys() {
+        let folder_key = FolderKey(TEST_FOLDER_KEY_DECODED);
+        let mut node = test_fetch_nodes_response().nodes[0].clone();
+        node.key = "ZZZZZZZZ:AAAAAAAAAAAAAAAAAAAAAA/AAAAAAAA:scxbPwzgRbyLF6h9hnfn0w".into();
+
+        let entries = node
+            .key_entries()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("failed to parse key entries");
+        assert!(
+            entries
+                == [
+                    ("ZZZZZZZZ", "AAAAAAAAAAAAAAAAAAAAAA"),
+                    ("AAAAAAAA", "scxbPwzgRbyLF6h9hnfn0w")
+                ]
+        );
+
+        let mut share_keys = std::collections::HashMap::new();
+        assert!(matches!(
+            node.decrypt_key_with_share_keys(&share_keys),
+            Err(DecodeAttributesError::MissingShareKey)
+        ));
+
+        share_keys.insert("AAAAAAAA".to_string(), folder_key);
+        let key = node
+            .decrypt_key_with_share_keys(&share_keys)
+            .expect("failed to decrypt key");
+        assert!(key == FolderKey(TEST_FETCH_NODES_DIRECTORY_KEY).into());
+
+        assert!(node.decrypt_share_key(&folder_key).is_none());
+        node.share_key = Some("scxbPwzgRbyLF6h9hnfn0w".into());
+        let share_key = node
+            .decrypt_share_key(&folder_key)
+            .expect("missing share key")
+            .expect("failed to decrypt share key");
+        assert!(share_key == FolderKey(TEST_FETCH_NODES_DIRECTORY_KEY));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn into_decoded() {
+        let folder_key = FolderKey(TEST_FOLDER_KEY_DECODED);
+        let mut response = test_fetch_nodes_response();
+        let expected: Vec<_> = response
+            .iter()
+            .map(|node| node.decode(&folder_key).expect("failed to decode node"))
+            .collect();
+        response
+            .nodes
+            .push(make_node("CCCCCCCC", "AAAAAAAA", FetchNodesNodeKind::File));
+
+        let decoded = response.into_decoded(&folder_key);
+        assert!(decoded.len() == 3);
+        for (decoded, expected) in decoded.iter().zip(expected.iter()) {
+            let decoded = decoded.as_ref().expect("failed to decode node");
+            assert!(decoded.id == expected.id);
+            assert!(decoded.parent_id == expected.parent_id);
+            assert!(decoded.name == expected.name);
+            assert!(decoded.key == expected.key);
+        }
+        assert!(decoded[2].is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn total_size() {
+        let mut response = test_fetch_nodes_response();
+        assert!(response.total_size() == 1234);
+
+        response
+            .nodes
+            .push(make_node("CCCCCCCC", "AAAAAAAA", FetchNodesNodeKind::File));
+        response.nodes[2].size = Some(1);
+        assert!(response.total_size() == 1235);
+
+        // Previous versions of "BBBBBBBB" must not be counted.
+        response
+            .nodes
+            .push(make_node("DDDDDDDD", "BBBBBBBB", FetchNodesNodeKind::File));
+        response.nodes[3].size = Some(100);
+        response
+            .nodes
+            .push(make_node("EEEEEEEE", "DDDDDDDD", FetchNodesNodeKind::File));
+        response.nodes[4].size = Some(10);
+        assert!(response.total_size() == 1235);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn unknown_fields() {
+        let mut response = test_fetch_nodes_response();
+        response.unknown.insert("b".into(), serde_json::Value::Null);
+        response.nodes[1]
+            .unknown
+            .insert("a2".into(), serde_json::Value::Null);
+
+        let response = ResponseData::FetchNodes(response);
+        assert!(response.unknown_fields() == ["b", "f.a2"]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn iterate_nodes() {
+        let response = test_fetch_nodes_response();
+
+        let ids: Vec<_> = response.iter().map(|node| node.id.as_str()).collect();
+        assert!(ids == ["AAAAAAAA", "BBBBBBBB"]);
+        assert!((&response).into_iter().count() == 2);
+        #[allow(deprecated)]
+        let files = response.files();
+        assert!(files.len() == response.nodes.len());
+
+        let ids: Vec<_> = response.into_iter().map(|node| node.id).collect();
+        assert!(ids == ["AAAAAAAA", "BBBBBBBB"]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn get_attributes_is_available() {
+        let mut response: GetAttributesResponse = serde_json::from_value(serde_json::json!({
+            "s": 1234,
+            "at": TEST_FILE_ENCODED_ATTRIBUTES,
+            "msd": 1,
+            "g": "https://example.com/dl/test",
+        }))
+        .expect("failed to parse response");
+        assert!(response.is_available());
+
+        response.size = 0;
+        assert!(!response.is_available());
+
+        response.size = 1234;
+        response.download_url = None;
+        assert!(!response.is_available());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn node_share_fields() {
+        let node = make_node("AAAAAAAA", TEST_FOLDER_ID, FetchNodesNodeKind::Directory);
+        assert!(!node.is_share_root());
+
+        let node: FetchNodesNode = serde_json::from_value(serde_json::json!({
+            "a": "",
+            "h": "AAAAAAAA",
+            "k": "",
+            "p": "",
+            "t": 1,
+            "ts": 0,
+            "u": "BBBBBBBBBBB",
+            "su": "BBBBBBBBBBB",
+            "sk": "CCCCCCCCCCCCCCCCCCCCCC",
+        }))
+        .expect("failed to parse node");
+        assert!(node.is_share_root());
+        assert!(node.share_user.as_deref() == Some("BBBBBBBBBBB"));
+        assert!(node.share_key.as_deref() == Some("CCCCCCCCCCCCCCCCCCCCCC"));
+        assert!(node.unknown.is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn node_versions() {
+        let response = FetchNodesResponse {
+            nodes: vec![
+                make_node("AAAAAAAA", "", FetchNodesNodeKind::Directory),
+                make_node("BBBBBBBB", "AAAAAAAA", FetchNodesNodeKind::File),
+                make_node("CCCCCCCC", "BBBBBBBB", FetchNodesNodeKind::File),
+                make_node("DDDDDDDD", "CCCCCCCC", FetchNodesNodeKind::File),
+                make_node("EEEEEEEE", "AAAAAAAA", FetchNodesNodeKind::File),
+            ],
+            noc: 1,
+            sn: String::new(),
+            st: String::new(),
+            unknown: Default::default(),
+        };
+
+        let versions: Vec<_> = response
+            .versions_of("BBBBBBBB")
+            .iter()
+            .map(|node| node.id.as_str())
+            .collect();
+        assert!(versions == ["CCCCCCCC", "DDDDDDDD"]);
+        assert!(response.has_versions("BBBBBBBB"));
+
+        assert!(response.versions_of("EEEEEEEE").is_empty());
+        assert!(!response.has_versions("EEEEEEEE"));
+        assert!(!response.has_versions("AAAAAAAA"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn node_kind_ordering() {
+        let mut entries = vec![
+            (FetchNodesNodeKind::File, "a.txt"),
+            (FetchNodesNodeKind::Directory, "b"),
+            (FetchNodesNodeKind::File, "c.txt"),
+            (FetchNodesNodeKind::TrashBin, ""),
+            (FetchNodesNodeKind::Directory, "a"),
+        ];
+        entries.sort();
+        assert!(
+            entries
+                == [
+                    (FetchNodesNodeKind::TrashBin, ""),
+                    (FetchNodesNodeKind::Directory, "a"),
+                    (FetchNodesNodeKind::Directory, "b"),
+                    (FetchNodesNodeKind::File, "a.txt"),
+                    (FetchNodesNodeKind::File, "c.txt"),
+                ]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn encode_attributes() {
+        let file_attributes = FileAttributes {
+            name: "Doxygen_docs.zip".into(),
+            c: None,
+            unknown: Default::default(),
+        };
+        let encoded = file_attributes
+            .encode(TEST_FILE_KEY_KEY_DECODED)
+            .expect("failed to encode attributes");
+        assert!(encoded == TEST_FILE_ENCODED_ATTRIBUTES);
+
+        let mut file_attributes = FileAttributes {
+            name: "a name that is long enough to span several blocks.txt".into(),
+            c: Some("fingerprint".into()),
+            unknown: Default::default(),
+        };
+        file_attributes
+            .unknown
+            .insert("extra".into(), serde_json::json!([1, 2, 3]));
+        let encoded = file_attributes
+            .encode(TEST_FILE_KEY_KEY_DECODED)
+            .expect("failed to encode attributes");
+        let response: GetAttributesResponse = serde_json::from_value(serde_json::json!({
+            "s": 0,
+            "at": encoded,
+            "msd": 1,
+        }))
+        .expect("failed to make response");
+        let decoded = response
+            .decode_attributes(TEST_FILE_KEY_KEY_DECODED)
+            .expect("failed to decode attributes");
+        assert!(decoded.name == file_attributes.name);
+        assert!(decoded.c == file_attributes.c);
+        assert!(decoded.unknown == file_attributes.unknown);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn upload_completion_token() {
+        assert!(UploadCompletionToken::from_chunk_response("") == Ok(None));
+        assert!(UploadCompletionToken::from_chunk_response("-3") == Err(ErrorCode::EAGAIN));
+        assert!(
+            UploadCompletionToken::from_chunk_response("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA")
+                == Ok(Some(UploadCompletionToken(
+                    "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".into()
+                )))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn decode_attributes_limit() {
+        let response: GetAttributesResponse = serde_json::from_value(serde_json::json!({
+            "s": 0,
+            "at": TEST_FILE_ENCODED_ATTRIBUTES,
+            "msd": 1,
+        }))
+        .expect("failed to make response");
+
+        let file_attributes = response
+            .decode_attributes(TEST_FILE_KEY_KEY_DECODED)
+            .expect("failed to decode attributes");
+        assert!(file_attributes.name == "Doxygen_docs.zip");
+
+        let error = response
+            .decode_attributes_with_limit(TEST_FILE_KEY_KEY_DECODED, 16)
+            .expect_err("attribute limit was not enforced");
+        assert!(matches!(
+            error,
+            DecodeAttributesError::AttributesTooLarge { max: 16, .. }
+        ));
+    }
+
+    #[cfg(feature = "reqwest")]
     #[tokio::test]
     async fn download_file() {
         let file_key = FileKey {
@@ -103,7 +892,8 @@ mod test {
 
         let client = Client::new();
         let commands = vec![Command::GetAttributes {
-            file_id: TEST_FILE_ID.into(),
+            public_file_id: Some(TEST_FILE_ID.into()),
+            node_id: None,
             include_download_url: Some(1),
         }];
         let mut response = client
@@ -0,0 +1,149 @@
+use crate::FileKey;
+use cbc::cipher::BlockEncrypt;
+use cbc::cipher::KeyInit;
+
+const BLOCK_SIZE: usize = 16;
+const BASE_CHUNK_SIZE: u64 = 128 * 1024;
+const MAX_CHUNK_SIZE_MULTIPLIER: u64 = 8;
+
+/// A calculator for the meta mac of a file.
+///
+/// The file is split into chunks of 128 KiB, 256 KiB, and so on up to 1 MiB,
+/// after which all chunks are 1 MiB.
+/// Each chunk is CBC-MACed with the file key, starting from the IV repeated twice.
+/// The chunk macs are then CBC-MACed together, and the result is folded into the 64 bit meta mac.
+#[derive(Clone)]
+pub(crate) struct MetaMacCalculator {
+    /// The cipher, using the file key
+    cipher: aes::Aes128,
+
+    /// The initial value of each chunk mac
+    chunk_mac_iv: u128,
+
+    /// The mac of the current chunk
+    chunk_mac: u128,
+
+    /// The mac of the file
+    file_mac: u128,
+
+    /// The current partial block
+    block: [u8; BLOCK_SIZE],
+
+    /// The number of bytes in the current partial block
+    block_len: usize,
+
+    /// The index of the current chunk
+    chunk_index: u64,
+
+    /// The number of bytes left in the current chunk
+    chunk_remaining: u64,
+
+    /// Whether the current chunk has any data
+    chunk_started: bool,
+}
+
+impl MetaMacCalculator {
+    /// Make a new MetaMacCalculator
+    pub(crate) fn new(file_key: &FileKey) -> Self {
+        let iv = file_key.nonce();
+        let mut chunk_mac_iv = [0; BLOCK_SIZE];
+        chunk_mac_iv[..iv.len()].copy_from_slice(&iv);
+        chunk_mac_iv[iv.len()..].copy_from_slice(&iv);
+        let chunk_mac_iv = u128::from_ne_bytes(chunk_mac_iv);
+
+        Self {
+            cipher: aes::Aes128::new(&file_key.key.to_ne_bytes().into()),
+            chunk_mac_iv,
+            chunk_mac: chunk_mac_iv,
+            file_mac: 0,
+            block: [0; BLOCK_SIZE],
+            block_len: 0,
+            chunk_index: 0,
+            chunk_remaining: chunk_size(0),
+            chunk_started: false,
+        }
+    }
+
+    /// Feed plaintext data into the calculator
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let len = std::cmp::min(BLOCK_SIZE - self.block_len, data.len());
+            let (head, tail) = data.split_at(len);
+            self.block[self.block_len..self.block_len + len].copy_from_slice(head);
+            self.block_len += len;
+            self.chunk_started = true;
+            data = tail;
+
+            if self.block_len == BLOCK_SIZE {
+                self.process_block();
+            }
+        }
+    }
+
+    /// Process the current block.
+    ///
+    /// Chunk sizes are multiples of the block size, so a block never spans two chunks.
+    fn process_block(&mut self) {
+        self.chunk_mac = self.encrypt(self.chunk_mac ^ u128::from_ne_bytes(self.block));
+        self.block = [0; BLOCK_SIZE];
+        self.block_len = 0;
+
+        self.chunk_remaining -= BLOCK_SIZE as u64;
+        if self.chunk_remaining == 0 {
+            self.finish_chunk();
+        }
+    }
+
+    /// Finish the current chunk, folding it into the file mac
+    fn finish_chunk(&mut self) {
+        self.file_mac = self.encrypt(self.file_mac ^ self.chunk_mac);
+        self.chunk_mac = self.chunk_mac_iv;
+        self.chunk_index += 1;
+        self.chunk_remaining = chunk_size(self.chunk_index);
+        self.chunk_started = false;
+    }
+
+    /// Encrypt a single block with the file key
+    fn encrypt(&self, block: u128) -> u128 {
+        let mut block = block.to_ne_bytes();
+        self.cipher.encrypt_block((&mut block).into());
+        u128::from_ne_bytes(block)
+    }
+
+    /// Get the meta mac of the data fed so far.
+    ///
+    /// A final partial block is zero-padded.
+    /// An empty file has no chunks, so its meta mac is zero.
+    pub(crate) fn finish(&self) -> u64 {
+        let mut calculator = self.clone();
+        if calculator.block_len != 0 {
+            calculator.process_block();
+        }
+        if calculator.chunk_started {
+            calculator.finish_chunk();
+        }
+
+        let file_mac = calculator.file_mac.to_ne_bytes();
+        let mut meta_mac = [0; 8];
+        for (i, byte) in meta_mac.iter_mut().enumerate() {
+            let offset = (i / 4) * 8 + (i % 4);
+            *byte = file_mac[offset] ^ file_mac[offset + 4];
+        }
+        u64::from_ne_bytes(meta_mac)
+    }
+}
+
+impl std::fmt::Debug for MetaMacCalculator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Avoid printing key material
+        f.debug_struct("MetaMacCalculator")
+            .field("chunk_index", &self.chunk_index)
+            .field("chunk_remaining", &self.chunk_remaining)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Get the size of the chunk at the given index
+fn chunk_size(index: u64) -> u64 {
+    BASE_CHUNK_SIZE * std::cmp::min(index + 1, MAX_CHUNK_SIZE_MULTIPLIER)
+}
@@ -0,0 +1,21 @@
+#[cfg(feature = "serde")]
+pub use crate::Client;
+#[cfg(feature = "serde")]
+pub use crate::Command;
+#[cfg(feature = "serde")]
+pub use crate::DecodedNode;
+#[cfg(feature = "easy")]
+pub use crate::EasyClient;
+pub use crate::EncryptingReader;
+pub use crate::Error;
+#[cfg(feature = "serde")]
+pub use crate::FetchNodesNodeKind;
+pub use crate::FileKey;
+pub use crate::FileOrFolderKey;
+pub use crate::FolderKey;
+#[cfg(feature = "easy")]
+pub use crate::GetAttributesBuilder;
+#[cfg(feature = "serde")]
+pub use crate::ResponseData;
+#[cfg(feature = "serde")]
+pub use crate::Transport;
@@ -0,0 +1,93 @@
+use crate::Error;
+use bytes::Bytes;
+use std::future::Future;
+use std::pin::Pin;
+use url::Url;
+
+/// The future returned by a transport
+pub type TransportFuture<'a> = Pin<Box<dyn Future<Output = Result<Bytes, Error>> + Send + 'a>>;
+
+/// An http transport, used to send api requests.
+///
+/// Implement this to use a custom http stack instead of reqwest.
+pub trait Transport: std::fmt::Debug + Send + Sync {
+    /// Send a POST request with a json body to the given url.
+    ///
+    /// This should return the response body.
+    /// Non-success http statuses should be returned as `Error::Http`, and timeouts as `Error::Timeout`.
+    fn post_json<'a>(&'a self, url: &'a Url, body: Bytes) -> TransportFuture<'a>;
+}
+
+#[cfg(feature = "reqwest")]
+impl Transport for reqwest::Client {
+    fn post_json<'a>(&'a self, url: &'a Url, body: Bytes) -> TransportFuture<'a> {
+        Box::pin(async move {
+            let response = self
+                .post(url.as_str())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body)
+                .send()
+                .await?
+                .error_for_status()?;
+
+            Ok(response.bytes().await?)
+        })
+    }
+}
+
+#[cfg(all(test, feature = "reqwest"))]
+mod test {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    /// Serve a single connection with the given raw http response, returning the server url.
+    async fn serve_once(response: &'static [u8]) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind");
+        let addr = listener.local_addr().expect("failed to get local addr");
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.expect("failed to accept");
+            let mut buffer = [0; 1024];
+            let _ = stream.read(&mut buffer).await.is_ok();
+            if !response.is_empty() {
+                let _ = stream.write_all(response).await.is_ok();
+            } else {
+                // Never respond
+                tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+            }
+        });
+
+        Url::parse(&format!("http://{addr}/")).expect("failed to parse url")
+    }
+
+    #[tokio::test]
+    async fn http_status_error() {
+        let url =
+            serve_once(b"HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\n\r\n").await;
+        let client = reqwest::Client::new();
+
+        let error = client
+            .post_json(&url, Bytes::from_static(b"[]"))
+            .await
+            .expect_err("request should fail");
+        assert!(matches!(error, Error::Http { status: 500 }));
+    }
+
+    #[tokio::test]
+    async fn timeout_error() {
+        let url = serve_once(b"").await;
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_millis(100))
+            .build()
+            .expect("failed to build client");
+
+        let error = client
+            .post_json(&url, Bytes::from_static(b"[]"))
+            .await
+            .expect_err("request should time out");
+        assert!(matches!(error, Error::Timeout));
+    }
+}
@@ -1,16 +1,42 @@
+#[cfg(feature = "serde")]
 mod command;
 mod error_code;
 mod file_key;
+mod file_or_folder_key;
 mod folder_key;
+#[cfg(feature = "serde")]
 mod response;
 
+#[cfg(feature = "serde")]
 pub use self::command::Command;
 pub use self::error_code::ErrorCode;
 pub use self::file_key::FileKey;
 pub use self::file_key::ParseError as FileKeyParseError;
+pub use self::file_or_folder_key::FileOrFolderKey;
+pub use self::file_or_folder_key::KeyKind;
 pub use self::folder_key::FolderKey;
 pub use self::folder_key::ParseError as FolderKeyParseError;
+#[cfg(feature = "serde")]
+pub use self::response::DecodeAttributesError;
+#[cfg(feature = "serde")]
+pub use self::response::DecodedNode;
+#[cfg(feature = "serde")]
 pub use self::response::FetchNodes as FetchNodesResponse;
+#[cfg(feature = "serde")]
+pub use self::response::FetchNodesNode;
+#[cfg(feature = "serde")]
+pub use self::response::FetchNodesNodeKind;
+#[cfg(feature = "serde")]
+pub use self::response::FileAttributes;
+#[cfg(feature = "serde")]
 pub use self::response::GetAttributes as GetAttributesResponse;
+#[cfg(feature = "serde")]
 pub use self::response::Response;
+#[cfg(feature = "serde")]
 pub use self::response::ResponseData;
+#[cfg(feature = "serde")]
+pub use self::response::UploadCompletionToken;
+#[cfg(feature = "serde")]
+pub use self::response::UploadUrl as UploadUrlResponse;
+#[cfg(feature = "serde")]
+pub use self::response::DEFAULT_MAX_ENCODED_ATTRIBUTES_LEN;
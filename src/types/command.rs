@@ -5,9 +5,18 @@ pub enum Command {
     /// Get the attributes of a file
     #[serde(rename = "g")]
     GetAttributes {
-        /// The id of the file
-        #[serde(rename = "p")]
-        file_id: String,
+        /// The public id of the file.
+        ///
+        /// This is the id used in public file links.
+        #[serde(rename = "p", skip_serializing_if = "Option::is_none")]
+        public_file_id: Option<String>,
+
+        /// The id of the node.
+        ///
+        /// This is the id of a node inside of a folder.
+        /// Accessing a node inside of a public folder requires the id of the folder to be passed as the reference node id.
+        #[serde(rename = "n", skip_serializing_if = "Option::is_none")]
+        node_id: Option<String>,
 
         ///  Set to Some(1) to include the download url in the response.
         #[serde(rename = "g")]
@@ -17,4 +26,12 @@ pub enum Command {
     /// Fetch the nodes
     #[serde(rename = "f")]
     FetchNodes { c: u8, r: u8 },
+
+    /// Get a url to upload a file to
+    #[serde(rename = "u")]
+    GetUploadUrl {
+        /// The size of the file
+        #[serde(rename = "s")]
+        size: u64,
+    },
 }
@@ -1,5 +1,6 @@
 /// An API Error
-#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct ErrorCode(i32);
 
 impl ErrorCode {
@@ -90,6 +91,12 @@ impl ErrorCode {
     /// Over Disk Quota Paywall
     pub const EPAYWALL: Self = ErrorCode(-29);
 
+    /// Make an ErrorCode from a raw code
+    #[cfg(feature = "serde")]
+    pub(crate) fn new(code: i32) -> Self {
+        Self(code)
+    }
+
     /// Get a human-friendly description if the error
     pub fn description(self) -> &'static str {
         match self {
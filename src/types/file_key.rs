@@ -1,6 +1,9 @@
+use rand::Rng;
+
 const KEY_SIZE: usize = 16;
+const NONCE_SIZE: usize = 8;
 const BASE64_LEN: usize = 43;
-const BASE64_DECODE_BUFFER_LEN: usize = ((BASE64_LEN * 2) + 3) / 4 * 3;
+const BASE64_DECODE_BUFFER_LEN: usize = (BASE64_LEN * 2).div_ceil(4) * 3;
 
 /// An error that may occur while parsing a FileKey.
 #[derive(Debug, thiserror::Error)]
@@ -24,12 +27,26 @@ pub enum ParseError {
 /// * The 128 bit AES key
 /// * The IV
 /// * The meta mac
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+///
+/// The derived equality comparison is not constant-time.
+/// Enable the `subtle` feature and use `ConstantTimeEq` when comparing secret keys.
+///
+/// The default key is all zeros.
+/// It is only a starting point for building a key in stages, and must not be used to encrypt real data.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(try_from = "String", into = "String")
+)]
 pub struct FileKey {
     /// The 128 bit AES key
     pub key: u128,
 
     /// The IV
+    ///
+    /// This is the initial AES-CTR counter block, stored in native byte order.
+    /// The first 8 bytes are the nonce and the last 8 bytes are the counter, which starts at zero.
     pub iv: u128,
 
     /// The meta mac
@@ -37,6 +54,18 @@ pub struct FileKey {
 }
 
 impl FileKey {
+    /// Generate a new random FileKey.
+    ///
+    /// The meta mac is zero, as it can only be computed after the file is encrypted.
+    pub fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+        Self {
+            key: rng.gen(),
+            iv: iv_from_nonce(rng.gen()),
+            meta_mac: 0,
+        }
+    }
+
     /// Make a FileKey from encoded bytes
     pub(crate) fn from_encoded_bytes(input: &[u8; KEY_SIZE * 2]) -> Self {
         let key = {
@@ -49,16 +78,57 @@ impl FileKey {
             n1 ^ n2
         };
 
-        let (iv, meta_mac) = input[KEY_SIZE..].split_at(std::mem::size_of::<u64>());
+        let (iv, meta_mac) = input[KEY_SIZE..].split_at(NONCE_SIZE);
 
         // Length is verified by split above.
-        let iv = u128::from(u64::from_ne_bytes(iv.try_into().unwrap()));
+        let iv = iv_from_nonce(iv.try_into().unwrap());
 
         // Length is verified by split and length of input
         let meta_mac = u64::from_ne_bytes(meta_mac.try_into().unwrap());
 
         Self { key, iv, meta_mac }
     }
+
+    /// Get the nonce, which is the first 8 bytes of the iv
+    pub(crate) fn nonce(&self) -> [u8; NONCE_SIZE] {
+        // Length is fixed by the array sizes
+        self.iv.to_ne_bytes()[..NONCE_SIZE].try_into().unwrap()
+    }
+
+    /// Encode this FileKey into bytes
+    pub(crate) fn to_encoded_bytes(&self) -> [u8; KEY_SIZE * 2] {
+        let mut n2 = [0; KEY_SIZE];
+        {
+            let (iv, meta_mac) = n2.split_at_mut(NONCE_SIZE);
+            iv.copy_from_slice(&self.nonce());
+            meta_mac.copy_from_slice(&self.meta_mac.to_ne_bytes());
+        }
+        let n1 = self.key ^ u128::from_ne_bytes(n2);
+
+        let mut output = [0; KEY_SIZE * 2];
+        output[..KEY_SIZE].copy_from_slice(&n1.to_ne_bytes());
+        output[KEY_SIZE..].copy_from_slice(&n2);
+        output
+    }
+}
+
+#[cfg(feature = "subtle")]
+impl subtle::ConstantTimeEq for FileKey {
+    fn ct_eq(&self, other: &Self) -> subtle::Choice {
+        self.key.to_ne_bytes().ct_eq(&other.key.to_ne_bytes())
+            & self.iv.to_ne_bytes().ct_eq(&other.iv.to_ne_bytes())
+            & self
+                .meta_mac
+                .to_ne_bytes()
+                .ct_eq(&other.meta_mac.to_ne_bytes())
+    }
+}
+
+/// Make an iv from a nonce, with the counter set to zero
+fn iv_from_nonce(nonce: [u8; NONCE_SIZE]) -> u128 {
+    let mut iv = [0; KEY_SIZE];
+    iv[..NONCE_SIZE].copy_from_slice(&nonce);
+    u128::from_ne_bytes(iv)
 }
 
 impl std::str::FromStr for FileKey {
@@ -83,3 +153,39 @@ impl std::str::FromStr for FileKey {
         Ok(Self::from_encoded_bytes(input.try_into().unwrap()))
     }
 }
+
+impl TryFrom<&str> for FileKey {
+    type Error = ParseError;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        input.parse()
+    }
+}
+
+impl TryFrom<String> for FileKey {
+    type Error = ParseError;
+
+    fn try_from(input: String) -> Result<Self, Self::Error> {
+        input.parse()
+    }
+}
+
+impl From<FileKey> for String {
+    fn from(key: FileKey) -> Self {
+        key.to_string()
+    }
+}
+
+impl std::fmt::Display for FileKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut buffer = [0; BASE64_LEN];
+        let len = base64::encode_config_slice(
+            self.to_encoded_bytes(),
+            base64::URL_SAFE_NO_PAD,
+            &mut buffer,
+        );
+
+        // Base64 is always valid utf8
+        f.write_str(std::str::from_utf8(&buffer[..len]).unwrap())
+    }
+}
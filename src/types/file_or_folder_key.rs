@@ -0,0 +1,98 @@
+use crate::FileKey;
+use crate::FolderKey;
+
+/// The kind of a key
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum KeyKind {
+    /// A file key
+    File,
+
+    /// A folder key
+    Folder,
+}
+
+/// Either a file key or a folder key
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum FileOrFolderKey {
+    /// A file key
+    File(FileKey),
+
+    /// A folder key
+    Folder(FolderKey),
+}
+
+impl FileOrFolderKey {
+    /// Generate a new random key of the given kind.
+    pub fn generate(kind: KeyKind) -> Self {
+        match kind {
+            KeyKind::File => Self::generate_file(),
+            KeyKind::Folder => Self::generate_folder(),
+        }
+    }
+
+    /// Generate a new random file key.
+    pub fn generate_file() -> Self {
+        Self::File(FileKey::generate())
+    }
+
+    /// Generate a new random folder key.
+    pub fn generate_folder() -> Self {
+        Self::Folder(FolderKey::generate())
+    }
+
+    /// Get the kind of this key
+    pub fn kind(&self) -> KeyKind {
+        match self {
+            Self::File(_) => KeyKind::File,
+            Self::Folder(_) => KeyKind::Folder,
+        }
+    }
+
+    /// Check if this is a file key
+    pub fn is_file_key(&self) -> bool {
+        matches!(self, Self::File(_))
+    }
+
+    /// Check if this is a folder key
+    pub fn is_folder_key(&self) -> bool {
+        matches!(self, Self::Folder(_))
+    }
+
+    /// Get the 128 bit AES key.
+    ///
+    /// This is the key used to encrypt the attributes of the node.
+    pub fn key(&self) -> u128 {
+        match self {
+            Self::File(file_key) => file_key.key,
+            Self::Folder(folder_key) => folder_key.0,
+        }
+    }
+
+    /// Get this as a file key, if it is one
+    pub fn as_file_key(&self) -> Option<&FileKey> {
+        match self {
+            Self::File(file_key) => Some(file_key),
+            Self::Folder(_) => None,
+        }
+    }
+
+    /// Get this as a folder key, if it is one
+    pub fn as_folder_key(&self) -> Option<&FolderKey> {
+        match self {
+            Self::File(_) => None,
+            Self::Folder(folder_key) => Some(folder_key),
+        }
+    }
+}
+
+impl From<FileKey> for FileOrFolderKey {
+    fn from(file_key: FileKey) -> Self {
+        Self::File(file_key)
+    }
+}
+
+impl From<FolderKey> for FileOrFolderKey {
+    fn from(folder_key: FolderKey) -> Self {
+        Self::Folder(folder_key)
+    }
+}
@@ -1,12 +1,16 @@
+use rand::Rng;
+
 const KEY_SIZE: usize = 16;
 const BASE64_LEN: usize = 22;
-const BASE64_DECODE_BUFFER_LEN: usize = ((BASE64_LEN * 2) + 3) / 4 * 3;
+const FULL_KEY_SIZE: usize = KEY_SIZE * 2;
+const FULL_BASE64_LEN: usize = 43;
+const BASE64_DECODE_BUFFER_LEN: usize = FULL_BASE64_LEN.div_ceil(4) * 3;
 
 /// An error that may occur while parsing a FolderKey.
 #[derive(Debug, thiserror::Error)]
 pub enum ParseError {
     /// The base64 string is the wrong size
-    #[error("invalid base64 length '{length}', expected length of '{BASE64_LEN}'")]
+    #[error("invalid base64 length '{length}', expected length of '{BASE64_LEN}' or '{FULL_BASE64_LEN}'")]
     InvalidBase64Length { length: usize },
 
     /// An error occured while decoding base64
@@ -14,22 +18,47 @@ pub enum ParseError {
     Base64Decode(#[from] base64::DecodeError),
 
     /// The key is the wrong size
-    #[error("invalid key length '{length}', expected length of '{KEY_SIZE}'")]
+    #[error("invalid key length '{length}', expected length of '{KEY_SIZE}' or '{FULL_KEY_SIZE}'")]
     InvalidLength { length: usize },
 }
 
 /// The encryption key for a folder.
 ///
 /// This is a 128 bit AES key.
+///
+/// Some folder links carry a full 256 bit file-style key instead.
+/// When parsed, its halves are XORed together into a 128 bit key, the same way file keys are.
+///
+/// The derived equality comparison is not constant-time.
+/// Enable the `subtle` feature and use `ConstantTimeEq` when comparing secret keys.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(try_from = "String", into = "String")
+)]
 pub struct FolderKey(pub u128);
 
+impl FolderKey {
+    /// Generate a new random FolderKey.
+    pub fn generate() -> Self {
+        Self(rand::thread_rng().gen())
+    }
+}
+
+#[cfg(feature = "subtle")]
+impl subtle::ConstantTimeEq for FolderKey {
+    fn ct_eq(&self, other: &Self) -> subtle::Choice {
+        self.0.to_ne_bytes().ct_eq(&other.0.to_ne_bytes())
+    }
+}
+
 impl std::str::FromStr for FolderKey {
     type Err = ParseError;
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
         let length = input.len();
-        if length < BASE64_LEN {
+        if !(BASE64_LEN..=FULL_BASE64_LEN).contains(&length) {
             return Err(ParseError::InvalidBase64Length { length });
         }
 
@@ -38,14 +67,56 @@ impl std::str::FromStr for FolderKey {
             base64::decode_config_slice(input, base64::URL_SAFE, &mut base64_decode_buffer)?;
         let input = &base64_decode_buffer[..decoded_len];
 
-        let length = input.len();
-        if length != KEY_SIZE {
-            return Err(ParseError::InvalidLength { length });
-        }
+        let key = match input.len() {
+            // Length check is done above
+            KEY_SIZE => u128::from_ne_bytes(input.try_into().unwrap()),
+            FULL_KEY_SIZE => {
+                let (a, b) = input.split_at(KEY_SIZE);
 
-        // Length check is done earlier
-        let key = u128::from_ne_bytes(input.try_into().unwrap());
+                // Length check is done above
+                let a = u128::from_ne_bytes(a.try_into().unwrap());
+                let b = u128::from_ne_bytes(b.try_into().unwrap());
+
+                a ^ b
+            }
+            length => {
+                return Err(ParseError::InvalidLength { length });
+            }
+        };
 
         Ok(Self(key))
     }
 }
+
+impl TryFrom<&str> for FolderKey {
+    type Error = ParseError;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        input.parse()
+    }
+}
+
+impl TryFrom<String> for FolderKey {
+    type Error = ParseError;
+
+    fn try_from(input: String) -> Result<Self, Self::Error> {
+        input.parse()
+    }
+}
+
+impl From<FolderKey> for String {
+    fn from(key: FolderKey) -> Self {
+        key.to_string()
+    }
+}
+
+impl std::fmt::Display for FolderKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut buffer = [0; BASE64_LEN];
+        let len =
+            base64::encode_config_slice(self.0.to_ne_bytes(), base64::URL_SAFE_NO_PAD, &mut buffer);
+
+        // Base64 is always valid utf8
+        f.write_str(std::str::from_utf8(&buffer[..len]).unwrap())
+    }
+}
@@ -1,16 +1,25 @@
 use crate::ErrorCode;
 use crate::FileKey;
+use crate::FileOrFolderKey;
 use crate::FolderKey;
 use crate::FolderKeyParseError;
 use cbc::cipher::BlockDecryptMut;
+use cbc::cipher::BlockEncryptMut;
 use cbc::cipher::KeyInit;
 use cbc::cipher::KeyIvInit;
 use std::collections::HashMap;
 use url::Url;
 
 type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
 type Aes128EcbDec = ecb::Decryptor<aes::Aes128>;
 
+/// The default maximum length of encoded attributes.
+///
+/// Attributes are usually small json objects,
+/// so this limit prevents a malicious response from forcing huge allocations.
+pub const DEFAULT_MAX_ENCODED_ATTRIBUTES_LEN: usize = 1024 * 1024;
+
 /// An api response
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 #[serde(untagged)]
@@ -45,6 +54,39 @@ pub enum ResponseData {
 
     /// Response for FetchNodes command
     FetchNodes(FetchNodes),
+
+    /// Response for a GetUploadUrl command
+    UploadUrl(UploadUrl),
+}
+
+impl ResponseData {
+    /// Get the name of the kind of response, for use in error messages.
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            Self::GetAttributes(_) => "GetAttributes",
+            Self::FetchNodes(_) => "FetchNodes",
+            Self::UploadUrl(_) => "UploadUrl",
+        }
+    }
+
+    /// Get the names of the fields that were not recognized while parsing, sorted.
+    ///
+    /// Fields of nested nodes are prefixed with the name of the field that contains them, like `f.xyz`.
+    pub fn unknown_fields(&self) -> Vec<String> {
+        let mut fields = std::collections::BTreeSet::new();
+        match self {
+            Self::GetAttributes(response) => fields.extend(response.unknown.keys().cloned()),
+            Self::FetchNodes(response) => {
+                fields.extend(response.unknown.keys().cloned());
+                for node in response.iter() {
+                    fields.extend(node.unknown.keys().map(|key| format!("f.{key}")));
+                }
+            }
+            Self::UploadUrl(response) => fields.extend(response.unknown.keys().cloned()),
+        }
+
+        fields.into_iter().collect()
+    }
 }
 
 /// An error that may occur while decoding attributes
@@ -78,9 +120,21 @@ pub enum DecodeAttributesError {
     #[error("key missing header")]
     KeyMissingHeader,
 
-    /// The key was the wrong size
-    #[error("invalid key length '{length}'")]
-    InvalidKeyLength { length: usize },
+    /// None of the key entries had a known share key
+    #[error("missing share key")]
+    MissingShareKey,
+
+    /// The key was the wrong size for the kind of node
+    #[error("invalid key length '{length}' for a {kind:?} node, expected length of '{expected}'")]
+    InvalidKeyLength {
+        length: usize,
+        expected: usize,
+        kind: FetchNodesNodeKind,
+    },
+
+    /// The encoded attributes are too large
+    #[error("encoded attributes length '{length}' exceeds the maximum of '{max}'")]
+    AttributesTooLarge { length: usize, max: usize },
 }
 
 /// File attributes
@@ -91,6 +145,7 @@ pub struct FileAttributes {
     pub name: String,
 
     /// ?
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub c: Option<String>,
 
     /// Unknown attributes
@@ -98,6 +153,30 @@ pub struct FileAttributes {
     pub unknown: HashMap<String, serde_json::Value>,
 }
 
+impl FileAttributes {
+    /// Encode these attributes with the given key.
+    ///
+    /// This is the inverse of decoding attributes.
+    pub fn encode(&self, key: u128) -> Result<String, serde_json::Error> {
+        let mut buffer = b"MEGA".to_vec();
+        serde_json::to_writer(&mut buffer, self)?;
+
+        // Zero-pad to the block size
+        let len = buffer.len();
+        buffer.resize(len.div_ceil(16) * 16, 0);
+
+        let cipher = Aes128CbcEnc::new(&key.to_ne_bytes().into(), &[0; 16].into());
+        let buffer_len = buffer.len();
+
+        // The buffer is padded to the block size above
+        let encrypted = cipher
+            .encrypt_padded_mut::<block_padding::NoPadding>(&mut buffer, buffer_len)
+            .unwrap();
+
+        Ok(base64::encode_config(encrypted, base64::URL_SAFE_NO_PAD))
+    }
+}
+
 /// GetAttributes command response
 #[derive(Debug, serde::Serialize, serde:: Deserialize)]
 pub struct GetAttributes {
@@ -121,21 +200,87 @@ pub struct GetAttributes {
 }
 
 impl GetAttributes {
+    /// Check whether this file can be downloaded.
+    ///
+    /// This is true when a download url was returned and the file is not empty.
+    pub fn is_available(&self) -> bool {
+        self.download_url.is_some() && self.size > 0
+    }
+
     /// Decode the encoded attributes
     pub fn decode_attributes(&self, key: u128) -> Result<FileAttributes, DecodeAttributesError> {
-        decode_attributes(&self.encoded_attributes, key)
+        self.decode_attributes_with_limit(key, DEFAULT_MAX_ENCODED_ATTRIBUTES_LEN)
+    }
+
+    /// Decode the encoded attributes, failing if they are longer than the given length.
+    pub fn decode_attributes_with_limit(
+        &self,
+        key: u128,
+        max_len: usize,
+    ) -> Result<FileAttributes, DecodeAttributesError> {
+        decode_attributes(&self.encoded_attributes, key, max_len)
+    }
+}
+
+/// GetUploadUrl command response
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct UploadUrl {
+    /// The url to upload the encrypted file to
+    #[serde(rename = "p")]
+    pub upload_url: Url,
+
+    /// Unknown attributes
+    #[serde(flatten)]
+    pub unknown: HashMap<String, serde_json::Value>,
+}
+
+/// The token returned by the upload server once all chunks of a file are uploaded.
+///
+/// This is needed to create the node for the uploaded file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UploadCompletionToken(pub String);
+
+impl UploadCompletionToken {
+    /// Parse the body of a response to an uploaded chunk.
+    ///
+    /// The upload server responds to chunks with an empty body until the upload is complete,
+    /// at which point it returns the completion token.
+    /// Errors are returned as a negative error code.
+    pub fn from_chunk_response(body: &str) -> Result<Option<Self>, ErrorCode> {
+        let body = body.trim();
+        if body.is_empty() {
+            return Ok(None);
+        }
+
+        if let Ok(code) = body.parse::<i32>() {
+            if code < 0 {
+                return Err(ErrorCode::new(code));
+            }
+        }
+
+        Ok(Some(Self(body.to_string())))
     }
 }
 
 /// FetchNodes command response
-#[derive(Debug, serde::Serialize, serde:: Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde:: Deserialize)]
 pub struct FetchNodes {
+    /// The nodes
+    ///
+    /// This contains the entire tree in a single response.
     #[serde(rename = "f")]
-    pub files: Vec<FetchNodesNode>,
+    pub nodes: Vec<FetchNodesNode>,
 
     pub noc: u8,
 
+    /// The sequence number of this node tree.
+    ///
+    /// This does not continue the listing.
+    /// Instead, it identifies the state of the tree,
+    /// and can be used to request the changes made after this response.
     pub sn: String,
+
+    /// The server state token
     pub st: String,
 
     /// Unknown attributes
@@ -143,6 +288,108 @@ pub struct FetchNodes {
     pub unknown: HashMap<String, serde_json::Value>,
 }
 
+impl FetchNodes {
+    /// Get the nodes.
+    #[deprecated(note = "use `nodes`")]
+    pub fn files(&self) -> &[FetchNodesNode] {
+        &self.nodes
+    }
+
+    /// Iterate over the nodes
+    pub fn iter(&self) -> std::slice::Iter<'_, FetchNodesNode> {
+        self.nodes.iter()
+    }
+
+    /// Decode all nodes, consuming this response.
+    ///
+    /// Each node is decoded independently, so one bad node does not prevent decoding the others.
+    pub fn into_decoded(
+        self,
+        folder_key: &FolderKey,
+    ) -> Vec<Result<DecodedNode, DecodeAttributesError>> {
+        self.into_iter()
+            .map(|node| node.into_decoded(folder_key))
+            .collect()
+    }
+
+    /// Get the total size of all file nodes, in bytes.
+    ///
+    /// Previous file versions are not counted.
+    pub fn total_size(&self) -> u64 {
+        self.iter()
+            .filter(|node| node.kind == FetchNodesNodeKind::File)
+            .filter(|node| !self.is_previous_version(node))
+            .filter_map(|node| node.size)
+            .sum()
+    }
+
+    /// Get the previous versions of the file node with the given id, newest first.
+    ///
+    /// Each previous version of a file is stored as a file node whose parent is the next newer version.
+    pub fn versions_of(&self, node_id: &str) -> Vec<&FetchNodesNode> {
+        let mut versions = Vec::new();
+        let mut current = match self.find_file(node_id) {
+            Some(node) => node,
+            None => return versions,
+        };
+
+        // Bound the walk by the number of nodes, in case a malformed response contains a cycle.
+        while versions.len() < self.nodes.len() {
+            match self.previous_version(&current.id) {
+                Some(node) => {
+                    versions.push(node);
+                    current = node;
+                }
+                None => break,
+            }
+        }
+
+        versions
+    }
+
+    /// Returns true if the file node with the given id has previous versions.
+    pub fn has_versions(&self, node_id: &str) -> bool {
+        self.find_file(node_id).is_some() && self.previous_version(node_id).is_some()
+    }
+
+    /// Find the file node with the given id
+    fn find_file(&self, node_id: &str) -> Option<&FetchNodesNode> {
+        self.nodes
+            .iter()
+            .find(|node| node.kind == FetchNodesNodeKind::File && node.id == node_id)
+    }
+
+    /// Returns true if the given node is a previous version of another file node.
+    fn is_previous_version(&self, node: &FetchNodesNode) -> bool {
+        node.kind == FetchNodesNodeKind::File && self.find_file(&node.parent_id).is_some()
+    }
+
+    /// Find the previous version of the file node with the given id
+    fn previous_version(&self, node_id: &str) -> Option<&FetchNodesNode> {
+        self.nodes
+            .iter()
+            .find(|node| node.kind == FetchNodesNodeKind::File && node.parent_id == node_id)
+    }
+}
+
+impl IntoIterator for FetchNodes {
+    type Item = FetchNodesNode;
+    type IntoIter = std::vec::IntoIter<FetchNodesNode>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.nodes.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a FetchNodes {
+    type Item = &'a FetchNodesNode;
+    type IntoIter = std::slice::Iter<'a, FetchNodesNode>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
 /// The kind of node
 #[derive(
     Debug,
@@ -172,8 +419,37 @@ pub enum FetchNodesNodeKind {
     TrashBin = 4,
 }
 
+impl FetchNodesNodeKind {
+    /// Get the rank of this kind, used for ordering.
+    fn sort_rank(self) -> u8 {
+        match self {
+            Self::Root => 0,
+            Self::Inbox => 1,
+            Self::TrashBin => 2,
+            Self::Directory => 3,
+            Self::File => 4,
+        }
+    }
+}
+
+/// Kinds are ordered with directories before files,
+/// so that sorting by kind and then name gives a directory-style listing.
+///
+/// The special directories come before normal directories.
+impl Ord for FetchNodesNodeKind {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.sort_rank().cmp(&other.sort_rank())
+    }
+}
+
+impl PartialOrd for FetchNodesNodeKind {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 /// A FetchNodes Node
-#[derive(Debug, serde::Serialize, serde:: Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde:: Deserialize)]
 pub struct FetchNodesNode {
     /// The attributes of the node
     #[serde(rename = "a")]
@@ -209,6 +485,18 @@ pub struct FetchNodesNode {
     #[serde(rename = "s")]
     pub size: Option<u64>,
 
+    /// The id of the user that shared this node.
+    ///
+    /// This is only present on the root node of an incoming share.
+    #[serde(rename = "su")]
+    pub share_user: Option<String>,
+
+    /// The encrypted share key.
+    ///
+    /// This is only present on the root node of a share.
+    #[serde(rename = "sk")]
+    pub share_key: Option<String>,
+
     /// Unknown attributes
     #[serde(flatten)]
     pub unknown: HashMap<String, serde_json::Value>,
@@ -220,42 +508,238 @@ impl FetchNodesNode {
         &self,
         folder_key: &FolderKey,
     ) -> Result<FileAttributes, DecodeAttributesError> {
+        self.decode_attributes_with_limit(folder_key, DEFAULT_MAX_ENCODED_ATTRIBUTES_LEN)
+    }
+
+    /// Decode the encoded attributes, failing if they are longer than the given length.
+    pub fn decode_attributes_with_limit(
+        &self,
+        folder_key: &FolderKey,
+        max_len: usize,
+    ) -> Result<FileAttributes, DecodeAttributesError> {
+        let key = self.decrypt_key(folder_key)?;
+        decode_attributes(&self.encoded_attributes, key.key(), max_len)
+    }
+
+    /// Iterate over the entries of the key of this node.
+    ///
+    /// The key is a `/`-separated list of `handle:key` entries,
+    /// where each key is encrypted with the key of the node with the given handle.
+    /// Nodes inside of nested shares have an entry for each share they are a part of.
+    pub fn key_entries(&self) -> impl Iterator<Item = Result<(&str, &str), DecodeAttributesError>> {
+        self.key.split('/').map(|entry| {
+            entry
+                .split_once(':')
+                .ok_or(DecodeAttributesError::KeyMissingHeader)
+        })
+    }
+
+    /// Decrypt the key of this node with the key of the folder that contains it.
+    ///
+    /// Directories have folder keys, while all other nodes have file keys.
+    /// This uses the first key entry.
+    pub fn decrypt_key(
+        &self,
+        folder_key: &FolderKey,
+    ) -> Result<FileOrFolderKey, DecodeAttributesError> {
         let (_, key) = self
-            .key
-            .split_once(':')
-            .ok_or(DecodeAttributesError::KeyMissingHeader)?;
-
-        let mut key = base64::decode_config(key, base64::URL_SAFE)?;
-        let cipher = Aes128EcbDec::new(&folder_key.0.to_ne_bytes().into());
-        let key = cipher
-            .decrypt_padded_mut::<block_padding::NoPadding>(&mut key)
-            .map_err(DecodeAttributesError::Decrypt)?;
-        let key_len = key.len();
-        let key: u128 = if self.kind == FetchNodesNodeKind::Directory {
-            if key_len != 16 {
-                return Err(DecodeAttributesError::InvalidKeyLength { length: key_len });
+            .key_entries()
+            .next()
+            .ok_or(DecodeAttributesError::KeyMissingHeader)??;
+
+        self.decrypt_key_entry(key, folder_key)
+    }
+
+    /// Decrypt the key of this node with the first key entry that has a known share key.
+    ///
+    /// The share keys are keyed by the handle of the share root node.
+    pub fn decrypt_key_with_share_keys(
+        &self,
+        share_keys: &HashMap<String, FolderKey>,
+    ) -> Result<FileOrFolderKey, DecodeAttributesError> {
+        for entry in self.key_entries() {
+            let (handle, key) = entry?;
+            if let Some(share_key) = share_keys.get(handle) {
+                return self.decrypt_key_entry(key, share_key);
             }
+        }
 
-            // Length check is done above
-            u128::from_ne_bytes(key.try_into().unwrap())
+        Err(DecodeAttributesError::MissingShareKey)
+    }
+
+    /// Decrypt the share key of this node with the key of the folder that contains it.
+    ///
+    /// Returns `None` if this node is not the root of a share.
+    pub fn decrypt_share_key(
+        &self,
+        folder_key: &FolderKey,
+    ) -> Option<Result<FolderKey, DecodeAttributesError>> {
+        let share_key = self.share_key.as_deref()?;
+
+        Some(decrypt_key_bytes(share_key, folder_key).and_then(|key| {
+            let length = key.len();
+            let key: [u8; 16] =
+                key.try_into()
+                    .map_err(|_| DecodeAttributesError::InvalidKeyLength {
+                        length,
+                        expected: 16,
+                        kind: self.kind,
+                    })?;
+            Ok(FolderKey(u128::from_ne_bytes(key)))
+        }))
+    }
+
+    /// Decrypt a single encoded key entry.
+    fn decrypt_key_entry(
+        &self,
+        key: &str,
+        folder_key: &FolderKey,
+    ) -> Result<FileOrFolderKey, DecodeAttributesError> {
+        let key = decrypt_key_bytes(key, folder_key)?;
+        let key_len = key.len();
+        let expected = if self.kind == FetchNodesNodeKind::Directory {
+            16
         } else {
-            if key_len != 32 {
-                return Err(DecodeAttributesError::InvalidKeyLength { length: key_len });
-            }
+            32
+        };
+        if key_len != expected {
+            return Err(DecodeAttributesError::InvalidKeyLength {
+                length: key_len,
+                expected,
+                kind: self.kind,
+            });
+        }
 
+        if self.kind == FetchNodesNodeKind::Directory {
+            // Length check is done above
+            Ok(FolderKey(u128::from_ne_bytes(key.try_into().unwrap())).into())
+        } else {
             // Length check is done above
-            FileKey::from_encoded_bytes(key.try_into().unwrap()).key
+            Ok(FileKey::from_encoded_bytes(key.as_slice().try_into().unwrap()).into())
+        }
+    }
+
+    /// Decrypt the key and decode the attributes of this node.
+    pub fn decode(&self, folder_key: &FolderKey) -> Result<DecodedNode, DecodeAttributesError> {
+        let (key, attributes) = self.decrypt_key_and_attributes(folder_key)?;
+
+        Ok(DecodedNode {
+            id: self.id.clone(),
+            parent_id: self.parent_id.clone(),
+            name: attributes.name,
+            kind: self.kind,
+            key,
+            size: self.size,
+        })
+    }
+
+    /// Decrypt the key and decode the attributes of this node, consuming it.
+    ///
+    /// This moves the ids into the decoded node instead of cloning them.
+    pub fn into_decoded(
+        self,
+        folder_key: &FolderKey,
+    ) -> Result<DecodedNode, DecodeAttributesError> {
+        let (key, attributes) = self.decrypt_key_and_attributes(folder_key)?;
+
+        Ok(DecodedNode {
+            id: self.id,
+            parent_id: self.parent_id,
+            name: attributes.name,
+            kind: self.kind,
+            key,
+            size: self.size,
+        })
+    }
+
+    /// Decrypt the key and decode the attributes of this node, without building a DecodedNode.
+    fn decrypt_key_and_attributes(
+        &self,
+        folder_key: &FolderKey,
+    ) -> Result<(FileOrFolderKey, FileAttributes), DecodeAttributesError> {
+        let key = self.decrypt_key(folder_key)?;
+        let attributes = decode_attributes(
+            &self.encoded_attributes,
+            key.key(),
+            DEFAULT_MAX_ENCODED_ATTRIBUTES_LEN,
+        )?;
+
+        Ok((key, attributes))
+    }
+
+    /// Returns true if this node is the root of a share.
+    pub fn is_share_root(&self) -> bool {
+        self.share_user.is_some() || self.share_key.is_some()
+    }
+
+    /// Get the public url of this node, inside of the given public folder.
+    ///
+    /// This returns None for the special root, inbox, and trash bin nodes.
+    pub fn public_url(&self, folder_id: &str, folder_key: &FolderKey) -> Option<String> {
+        let kind = match self.kind {
+            FetchNodesNodeKind::File => "file",
+            FetchNodesNodeKind::Directory => "folder",
+            FetchNodesNodeKind::Root | FetchNodesNodeKind::Inbox | FetchNodesNodeKind::TrashBin => {
+                return None
+            }
         };
 
-        decode_attributes(&self.encoded_attributes, key)
+        Some(format!(
+            "https://mega.nz/folder/{folder_id}#{folder_key}/{kind}/{}",
+            self.id
+        ))
     }
 }
 
+/// A decoded FetchNodes node
+#[derive(Debug, Clone)]
+pub struct DecodedNode {
+    /// The id of the node
+    pub id: String,
+
+    /// The id of the parent node
+    pub parent_id: String,
+
+    /// The name of the node
+    pub name: String,
+
+    /// The kind of the node
+    pub kind: FetchNodesNodeKind,
+
+    /// The decrypted key of the node
+    pub key: FileOrFolderKey,
+
+    /// The size of the node
+    pub size: Option<u64>,
+}
+
+/// Decode and decrypt an encrypted key with a folder key.
+fn decrypt_key_bytes(key: &str, folder_key: &FolderKey) -> Result<Vec<u8>, DecodeAttributesError> {
+    let mut key = base64::decode_config(key, base64::URL_SAFE)?;
+    let cipher = Aes128EcbDec::new(&folder_key.0.to_ne_bytes().into());
+    let len = cipher
+        .decrypt_padded_mut::<block_padding::NoPadding>(&mut key)
+        .map_err(DecodeAttributesError::Decrypt)?
+        .len();
+    key.truncate(len);
+
+    Ok(key)
+}
+
 /// Decode the encoded attributes
 fn decode_attributes(
     encoded_attributes: &str,
     key: u128,
+    max_len: usize,
 ) -> Result<FileAttributes, DecodeAttributesError> {
+    let length = encoded_attributes.len();
+    if length > max_len {
+        return Err(DecodeAttributesError::AttributesTooLarge {
+            length,
+            max: max_len,
+        });
+    }
+
     let mut encoded_attributes = base64::decode_config(encoded_attributes, base64::URL_SAFE)?;
 
     let cipher = Aes128CbcDec::new(&key.to_ne_bytes().into(), &[0; 16].into());